@@ -0,0 +1,69 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use kecs::rollback::RollbackSnapshot;
+use kecs::world::World;
+
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct Position {
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct Velocity {
+    x: f32,
+    y: f32,
+    z: f32,
+}
+
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+struct Health(u32);
+
+const ENTITIES: usize = 50_000;
+
+fn world() -> World {
+    let mut world = World::new();
+    world.register_pod::<Position>("Position").unwrap();
+    world.register_pod::<Velocity>("Velocity").unwrap();
+    world.register_pod::<Health>("Health").unwrap();
+
+    for i in 0..ENTITIES {
+        let position = Position {
+            x: i as f32,
+            y: 0.0,
+            z: 0.0,
+        };
+        let velocity = Velocity {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        // Spread the entities over a few archetypes.
+        match i % 3 {
+            0 => world.spawn((position,)),
+            1 => world.spawn((position, velocity)),
+            _ => world.spawn((position, velocity, Health(100))),
+        };
+    }
+    world
+}
+
+fn rollback(c: &mut Criterion) {
+    let mut world = world();
+    let mut snapshot = RollbackSnapshot::new();
+
+    c.bench_function("rollback_snapshot_50k", |b| {
+        b.iter(|| world.rollback_snapshot_into(&mut snapshot).unwrap())
+    });
+
+    world.rollback_snapshot_into(&mut snapshot).unwrap();
+    c.bench_function("rollback_restore_50k", |b| {
+        b.iter(|| world.rollback(&snapshot).unwrap())
+    });
+}
+
+criterion_group!(benches, rollback);
+criterion_main!(benches);
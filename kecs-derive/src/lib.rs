@@ -0,0 +1,148 @@
+//! Derive macros for kecs.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned, ToTokens};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Index};
+
+/// Implements `ComponentBundle` for a struct.
+/// Every field is a component unless it is marked `#[bundle]`, in which case it is a nested bundle
+/// whose components are flattened into this one.
+/// Repeated component types among the fields and the elements of tuple `#[bundle]` fields
+/// are rejected at compile time. Repeats between two other `#[bundle]` fields,
+/// or between such a field and a component field, are only caught when spawning.
+#[proc_macro_derive(Bundle, attributes(bundle))]
+pub fn derive_bundle(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match bundle_impl(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn bundle_impl(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "`Bundle` can only be derived for structs",
+            ))
+        }
+    };
+
+    // The bundle type of each field and the expression moving it out of `self`.
+    let mut bundles = Vec::new();
+    let mut values = Vec::new();
+    // Component fields and the elements of tuple `#[bundle]` fields, checked for duplicates.
+    let mut components: Vec<&syn::Type> = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => ident.to_token_stream(),
+            None => Index::from(i).to_token_stream(),
+        };
+        let ty = &field.ty;
+        if field.attrs.iter().any(|a| a.path().is_ident("bundle")) {
+            bundles.push(quote!(#ty));
+            values.push(quote!(self.#member));
+            // The components of other nested bundles are not visible here and are checked when spawning.
+            if let syn::Type::Tuple(tuple) = ty {
+                for element in tuple.elems.iter() {
+                    add_component(&mut components, element)?;
+                }
+            }
+        } else {
+            add_component(&mut components, ty)?;
+            bundles.push(quote!((#ty,)));
+            values.push(quote!((self.#member,)));
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut predicates: Vec<TokenStream2> = where_clause
+        .map(|w| w.predicates.iter().map(|p| p.to_token_stream()).collect())
+        .unwrap_or_default();
+    predicates.push(quote!(Self: 'static + Send + Sync));
+    for bundle in bundles.iter() {
+        predicates.push(quote!(#bundle: ::kecs::world::ComponentBundle));
+    }
+
+    // Different spellings of the same type, such as through an alias, are caught by giving each
+    // component type an impl of the same trait: a repeated type is a conflicting impl.
+    // The impls are outside the struct's generics, so types using its generic parameters are left out.
+    let generics: Vec<String> = input
+        .generics
+        .params
+        .iter()
+        .filter_map(|param| match param {
+            syn::GenericParam::Type(param) => Some(param.ident.to_string()),
+            syn::GenericParam::Const(param) => Some(param.ident.to_string()),
+            syn::GenericParam::Lifetime(_) => None,
+        })
+        .collect();
+    let impls = components
+        .iter()
+        .filter(|ty| !mentions_any(ty.to_token_stream(), &generics))
+        .map(|ty| quote_spanned!(ty.span()=> impl DuplicateComponentInBundle for #ty {}));
+    let distinct = quote! {
+        const _: () = {
+            trait DuplicateComponentInBundle {}
+            #(#impls)*
+        };
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::kecs::world::ComponentBundle for #name #ty_generics
+        where
+            #(#predicates,)*
+        {
+            fn component_ids(ids: &mut ::std::vec::Vec<::kecs::component::ComponentId>) {
+                #(<#bundles as ::kecs::world::ComponentBundle>::component_ids(ids);)*
+            }
+
+            fn register_types(registry: &mut ::kecs::registry::ComponentRegistry) {
+                #(<#bundles as ::kecs::world::ComponentBundle>::register_types(registry);)*
+            }
+
+            fn add_columns(archetype: &mut ::kecs::archetype::Archetype) {
+                #(<#bundles as ::kecs::world::ComponentBundle>::add_columns(archetype);)*
+            }
+
+            fn push_components(self, archetype: &mut ::kecs::archetype::Archetype) {
+                #(::kecs::world::ComponentBundle::push_components(#values, archetype);)*
+            }
+        }
+
+        #distinct
+    })
+}
+
+/// Adds a component type to the bundle, failing if it is already there.
+fn add_component<'a>(components: &mut Vec<&'a syn::Type>, ty: &'a syn::Type) -> syn::Result<()> {
+    let name = ty.to_token_stream().to_string();
+    if components
+        .iter()
+        .any(|other| other.to_token_stream().to_string() == name)
+    {
+        return Err(syn::Error::new(
+            ty.span(),
+            format!(
+                "the component type `{}` appears more than once in the bundle",
+                name
+            ),
+        ));
+    }
+    components.push(ty);
+    Ok(())
+}
+
+/// Returns true if any of the identifiers appears in the tokens.
+fn mentions_any(tokens: TokenStream2, idents: &[String]) -> bool {
+    tokens.into_iter().any(|token| match token {
+        proc_macro2::TokenTree::Ident(ident) => idents.iter().any(|i| ident == i),
+        proc_macro2::TokenTree::Group(group) => mentions_any(group.stream(), idents),
+        _ => false,
+    })
+}
@@ -1,7 +1,6 @@
 use crate::entity::*;
 use crate::component::*;
 use std::sync::RwLock;
-use std::any::{TypeId};
 
 /// An archetype stores entities with the same set of components.
 #[doc(hidden)]
@@ -10,6 +9,12 @@ pub struct Archetype {
     pub(crate) components: Vec<ComponentStore>,
 }
 
+impl Default for Archetype {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[allow(dead_code)]
 impl Archetype {
     pub fn new() -> Self {
@@ -50,24 +55,30 @@ impl Archetype {
         self.mutable_component_store(component_index).push(t)
     }
 
-    pub fn get_component_mut<T: 'static>(
-        &mut self,
-        index: EntityId,
-    ) -> Result<&mut T, EntityMissingComponent> {
-        let type_id = TypeId::of::<T>();
+    /// Pushes a component onto the column storing its type.
+    pub(crate) fn push_component<T: 'static>(&mut self, t: T) {
+        let id = ComponentId::of::<T>();
+        let component_index = self
+            .components
+            .binary_search_by_key(&id, |c| c.id)
+            .unwrap();
+        self.push(component_index, t);
+    }
+
+    /// Returns `None` if this archetype does not store a `T`.
+    pub fn get_component_mut<T: 'static>(&mut self, index: EntityId) -> Option<&mut T> {
+        let id = ComponentId::of::<T>();
         let mut component_index = None;
         for (i, c) in self.components.iter().enumerate() {
-            if c.type_id == type_id {
+            if c.id == id {
                 component_index = Some(i);
                 break;
             }
         }
 
-        if let Some(component_index) = component_index {
-            Ok(&mut self.mutable_component_store(component_index)[index as usize])
-        } else {
-            Err(EntityMissingComponent::new::<T>(index))
-        }
+        component_index.map(move |component_index| {
+            &mut self.mutable_component_store(component_index)[index as usize]
+        })
     }
 
     /// Removes the component from an entity and pushes it to the other archetype
@@ -91,5 +102,9 @@ impl Archetype {
     pub fn len(&mut self) -> usize {
         self.entities.len()
     }
+
+    pub fn is_empty(&mut self) -> bool {
+        self.entities.is_empty()
+    }
 }
 
@@ -0,0 +1,203 @@
+//! Duplicating entities within a world or into another world.
+//!
+//! Components are copied with the clone functions recorded by `register_clone`
+//! and dynamic components are copied byte for byte.
+//! An entity with any other component type that was not registered is not cloned at all.
+//! `Parent`, `Children` and relation components describe links between entities rather than values,
+//! so they are not copied: a clone in the same world is made a child of the original's parent,
+//! and has no children or relations of its own.
+
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+use crate::hierarchy::*;
+use crate::world::*;
+
+impl World {
+    /// Spawns a copy of an entity's components.
+    /// An error listing every component type not registered with `register_clone` is returned
+    /// if there are any, and nothing is spawned.
+    pub fn clone_entity(&mut self, entity: Entity) -> Result<Entity, Error> {
+        let stores = self.clone_stores(entity)?;
+        let parent = self.get::<Parent>(entity).map(|parent| parent.get()).ok();
+
+        let clone = self.reserve_entity();
+        self.spawn_stores(clone.index, stores);
+        if let Some(parent) = parent {
+            self.set_parent(clone, parent)?;
+        }
+        Ok(clone)
+    }
+
+    /// Spawns a copy of an entity's components in another world.
+    /// `Entity` handles held by the copied components are not changed, so they still refer to entities in this world.
+    /// Dynamic components are matched up by name, and registered in `other` if it does not have them yet.
+    /// An error listing every component type not registered with `register_clone` is returned
+    /// if there are any, and nothing is spawned.
+    pub fn clone_entity_into(&self, entity: Entity, other: &mut World) -> Result<Entity, Error> {
+        let mut stores = self.clone_stores(entity)?;
+        other.check_imports(self, stores.iter().map(|c| c.id))?;
+        for c in stores.iter_mut() {
+            c.id = other.import_component(self, c.id);
+        }
+
+        let clone = other.reserve_entity();
+        other.spawn_stores(clone.index, stores);
+        Ok(clone)
+    }
+
+    /// Clones every component of an entity that holds a value into single row `ComponentStore`s.
+    fn clone_stores(&self, entity: Entity) -> Result<Vec<ComponentStore>, Error> {
+        let entity_info = self
+            .entity_info(entity)
+            .ok_or_else(|| NoSuchEntity::new(entity))?;
+        let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+        let row = entity_info.location.index_in_archetype as usize;
+
+        let mut stores = Vec::with_capacity(archetype.components.len());
+        let mut not_cloneable = Vec::new();
+        for c in archetype.components.iter() {
+            if self.is_link_component(c.id) {
+                continue;
+            }
+            match self
+                .registry
+                .info(c.id)
+                .and_then(|info| info.clone_component(&*c.data, row))
+            {
+                Some(store) => stores.push(store?),
+                None => not_cloneable.push(self.component_name(c.id)),
+            }
+        }
+        if !not_cloneable.is_empty() {
+            return Err(NotCloneable::new(not_cloneable).into());
+        }
+        Ok(stores)
+    }
+
+    /// Returns true for the hierarchy and relation components that link entities together.
+    fn is_link_component(&self, id: ComponentId) -> bool {
+        id == ComponentId::of::<Parent>()
+            || id == ComponentId::of::<Children>()
+            || self.relation_hooks.contains_key(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Health(u32);
+    #[derive(Debug, Clone, PartialEq)]
+    struct Name(String);
+    #[derive(Debug, PartialEq)]
+    struct Socket(u16);
+    struct Likes;
+
+    #[test]
+    fn test_clone_entity() {
+        let mut world = World::new();
+        world.register_clone::<Health>("Health").unwrap();
+        world.register_clone::<Name>("Name").unwrap();
+        let parent = world.spawn((Name("parent".to_string()),));
+        let a = world.spawn((Health(5), Name("a".to_string())));
+        world.set_parent(a, parent).unwrap();
+        let child = world.spawn((Health(1),));
+        world.set_parent(child, a).unwrap();
+        world.add_relation(a, Likes, parent).unwrap();
+
+        let b = world.clone_entity(a).unwrap();
+        assert_ne!(a, b);
+        assert_eq!(*world.get::<Health>(b).unwrap(), Health(5));
+        assert_eq!(world.get::<Name>(b).unwrap().0, "a");
+        world.get_component_mut::<Health>(b).unwrap().0 = 6;
+        assert_eq!(*world.get::<Health>(a).unwrap(), Health(5));
+
+        // The clone shares the original's parent but not its children or relations.
+        assert_eq!(world.get::<Parent>(b).unwrap().get(), parent);
+        assert_eq!(&**world.get::<Children>(parent).unwrap(), &[a, b]);
+        assert!(world.get::<Children>(b).is_err());
+        assert!(world.targets::<Likes>(b).is_empty());
+        assert_eq!(world.sources::<Likes>(parent), vec![a]);
+
+        let c = world.spawn((Health(1), Socket(80), Name("c".to_string())));
+        let error = world.clone_entity(c).unwrap_err();
+        match &error {
+            Error::NotCloneable(e) => {
+                assert_eq!(e.names(), &[std::any::type_name::<Socket>().to_string()])
+            }
+            _ => panic!("unexpected error {}", error),
+        }
+        assert!(error.to_string().contains("Socket"));
+        // Nothing was spawned for the failed clone.
+        assert_eq!(world.query::<(&Health,)>().unwrap().iter().count(), 4);
+    }
+
+    #[test]
+    fn test_clone_entity_into() {
+        let mut world = World::new();
+        world.register_clone::<Health>("Health").unwrap();
+        world.register_debug::<Health>("Health").unwrap();
+        let a = world.spawn((Health(3),));
+
+        let mut other = World::new();
+        other.spawn((Name("x".to_string()),));
+        let b = world.clone_entity_into(a, &mut other).unwrap();
+        assert_eq!(*other.get::<Health>(b).unwrap(), Health(3));
+        assert_eq!(*world.get::<Health>(a).unwrap(), Health(3));
+
+        // The other world learns how to handle the type.
+        let id = other.registry().id_of("Health").unwrap();
+        assert_eq!(other.debug_component(b, id).unwrap(), "Health(3)");
+        let c = other.clone_entity(b).unwrap();
+        assert_eq!(*other.get::<Health>(c).unwrap(), Health(3));
+
+        let d = world.spawn((Health(3), Socket(1)));
+        assert!(matches!(
+            world.clone_entity_into(d, &mut other),
+            Err(Error::NotCloneable(_))
+        ));
+    }
+
+    #[test]
+    fn test_clone_dynamic_components() {
+        let mut world = World::new();
+        world.register_clone::<Health>("Health").unwrap();
+        let bytes = world
+            .register_dynamic_component("Bytes", std::alloc::Layout::new::<[u8; 4]>())
+            .unwrap();
+        let a = world.spawn((Health(3),));
+        world.insert_dynamic(a, bytes, &[1, 2, 3, 4]).unwrap();
+
+        let b = world.clone_entity(a).unwrap();
+        assert_eq!(&*world.get_dynamic(b, bytes).unwrap(), &[1, 2, 3, 4]);
+        world.get_dynamic_mut(b, bytes).unwrap()[0] = 9;
+        assert_eq!(&*world.get_dynamic(a, bytes).unwrap(), &[1, 2, 3, 4]);
+
+        // The other world already uses the first dynamic id for a different component.
+        let mut other = World::new();
+        let word = other
+            .register_dynamic_component("Word", std::alloc::Layout::new::<u16>())
+            .unwrap();
+        let c = world.clone_entity_into(a, &mut other).unwrap();
+        let other_bytes = other.registry().id_of("Bytes").unwrap();
+        assert_ne!(other_bytes, word);
+        assert_eq!(&*other.get_dynamic(c, other_bytes).unwrap(), &[1, 2, 3, 4]);
+        assert!(other.get_dynamic(c, word).is_err());
+
+        let prefab = world.prefab_from_entity(b).unwrap();
+        let d = world.instantiate(&prefab);
+        assert_eq!(&*world.get_dynamic(d, bytes).unwrap(), &[9, 2, 3, 4]);
+
+        let mut taken = World::new();
+        taken
+            .register_dynamic_component("Bytes", std::alloc::Layout::new::<u8>())
+            .unwrap();
+        assert!(matches!(
+            world.clone_entity_into(a, &mut taken),
+            Err(Error::ComponentNameConflict(_))
+        ));
+    }
+}
@@ -1,3 +1,4 @@
+use std::alloc::Layout;
 use std::any::{Any, TypeId};
 use std::sync::RwLock;
 
@@ -6,15 +7,86 @@ use crate::entity::*;
 pub trait Component: Sync + Send + 'static {}
 impl<T: Sync + Send + 'static> Component for T {}
 
+/// Identifies a component type within a `World`.
+/// Columns in an archetype are sorted by their `ComponentId`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ComponentId(ComponentKind);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum ComponentKind {
+    Static(TypeId),
+    /// A component defined at runtime with `World::register_dynamic_component`.
+    Dynamic(u32),
+}
+
+impl ComponentId {
+    pub fn of<T: 'static>() -> Self {
+        Self(ComponentKind::Static(TypeId::of::<T>()))
+    }
+
+    pub(crate) fn dynamic(index: u32) -> Self {
+        Self(ComponentKind::Dynamic(index))
+    }
+
+    /// The Rust type of the component, or `None` for a dynamic component.
+    pub fn type_id(&self) -> Option<TypeId> {
+        match self.0 {
+            ComponentKind::Static(type_id) => Some(type_id),
+            ComponentKind::Dynamic(_) => None,
+        }
+    }
+
+    /// Returns true if the component was defined at runtime rather than by a Rust type.
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self.0, ComponentKind::Dynamic(_))
+    }
+}
+
 /// The ComponentVec trait is used to define a set of things that can be done on
 /// an Any without knowing its exact type.
 pub trait ComponentVec: Sync + Send {
     fn to_any(&self) -> &dyn Any;
     fn to_any_mut(&mut self) -> &mut dyn Any;
     fn len(&mut self) -> usize;
+    fn is_empty(&mut self) -> bool {
+        self.len() == 0
+    }
     fn swap_remove(&mut self, index: EntityId);
+    fn clear(&mut self);
     fn migrate(&mut self, entity_index: EntityId, other_archetype: &mut dyn ComponentVec);
+    /// Moves every component to the end of another column of the same type, leaving this one empty.
+    fn append(&mut self, other: &mut dyn ComponentVec);
     fn new_same_type(&self) -> Box<dyn ComponentVec + Send + Sync>;
+    /// Locks the column for reading without knowing its type.
+    /// Returns `None` if the column is already mutably borrowed.
+    fn try_read_erased(&self) -> Option<ErasedColumn<'_>>;
+    /// Locks the column for writing without knowing its type.
+    /// Returns `None` if the column is already borrowed.
+    fn try_write_erased(&self) -> Option<ErasedColumn<'_>>;
+}
+
+/// Anything that must be kept alive, such as a lock guard.
+trait Held {}
+impl<T: ?Sized> Held for T {}
+
+/// A column locked for reading or writing, seen as rows of untyped memory.
+/// The column stays locked until this is dropped.
+#[doc(hidden)]
+pub struct ErasedColumn<'a> {
+    _guard: Box<dyn Held + 'a>,
+    ptr: *mut u8,
+    stride: usize,
+    len: usize,
+}
+
+impl ErasedColumn<'_> {
+    /// Returns a pointer to the component in a row.
+    /// It may only be written through if the column was locked for writing.
+    pub(crate) fn row_ptr(&self, row: usize) -> *mut u8 {
+        assert!(row < self.len);
+        // A stride of zero leaves the pointer dangling but aligned, which is valid for zero sized types.
+        self.ptr.wrapping_add(row * self.stride)
+    }
 }
 
 // This could be made unchecked in the future if there's a high degree of confidence in everything else.
@@ -43,19 +115,188 @@ impl<T: Component> ComponentVec for RwLock<Vec<T>> {
         self.get_mut().unwrap().swap_remove(index as usize);
     }
 
+    fn clear(&mut self) {
+        self.get_mut().unwrap().clear();
+    }
+
     fn migrate(&mut self, entity_index: EntityId, other_component_vec: &mut dyn ComponentVec) {
         let data: T = self.get_mut().unwrap().swap_remove(entity_index as usize);
         component_vec_to_mut(other_component_vec).push(data);
     }
 
+    fn append(&mut self, other: &mut dyn ComponentVec) {
+        component_vec_to_mut::<T>(other).append(self.get_mut().unwrap());
+    }
+
     fn new_same_type(&self) -> Box<dyn ComponentVec + Send + Sync> {
         Box::new(RwLock::new(Vec::<T>::new()))
     }
+
+    fn try_read_erased(&self) -> Option<ErasedColumn<'_>> {
+        let guard = self.try_read().ok()?;
+        Some(ErasedColumn {
+            ptr: guard.as_ptr() as *mut u8,
+            stride: std::mem::size_of::<T>(),
+            len: guard.len(),
+            _guard: Box::new(guard),
+        })
+    }
+
+    fn try_write_erased(&self) -> Option<ErasedColumn<'_>> {
+        let mut guard = self.try_write().ok()?;
+        Some(ErasedColumn {
+            ptr: guard.as_mut_ptr() as *mut u8,
+            stride: std::mem::size_of::<T>(),
+            len: guard.len(),
+            _guard: Box::new(guard),
+        })
+    }
+}
+
+/// Storage is allocated in blocks of this type so rows can be aligned to up to 16 bytes.
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C, align(16))]
+struct AlignedBlock([u8; 16]);
+
+/// The largest alignment a dynamic component can have.
+pub(crate) const MAX_DYNAMIC_ALIGN: usize = std::mem::align_of::<AlignedBlock>();
+
+/// Stores dynamic components, which are plain bytes with a runtime `Layout`.
+/// Each row starts at a multiple of the layout's padded size.
+pub(crate) struct DynamicVec {
+    size: usize,
+    stride: usize,
+    len: usize,
+    blocks: Vec<AlignedBlock>,
+}
+
+impl DynamicVec {
+    pub(crate) fn new(layout: Layout) -> Self {
+        assert!(layout.align() <= MAX_DYNAMIC_ALIGN);
+        Self {
+            size: layout.size(),
+            stride: layout.pad_to_align().size(),
+            len: 0,
+            blocks: Vec::new(),
+        }
+    }
+
+    fn set_len(&mut self, len: usize) {
+        self.len = len;
+        let bytes = len * self.stride;
+        self.blocks
+            .resize(bytes.div_ceil(MAX_DYNAMIC_ALIGN), AlignedBlock([0; 16]));
+    }
+
+    pub(crate) fn row(&self, row: usize) -> &[u8] {
+        assert!(row < self.len);
+        let start = row * self.stride;
+        &bytemuck::cast_slice(&self.blocks)[start..start + self.size]
+    }
+
+    pub(crate) fn row_mut(&mut self, row: usize) -> &mut [u8] {
+        assert!(row < self.len);
+        let start = row * self.stride;
+        &mut bytemuck::cast_slice_mut(&mut self.blocks)[start..start + self.size]
+    }
+
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        self.set_len(self.len + 1);
+        self.row_mut(self.len - 1).copy_from_slice(bytes);
+    }
+
+    fn swap_remove(&mut self, row: usize) {
+        assert!(row < self.len);
+        let last = self.len - 1;
+        if row != last {
+            let stride = self.stride;
+            bytemuck::cast_slice_mut::<_, u8>(&mut self.blocks)
+                .copy_within(last * stride..(last + 1) * stride, row * stride);
+        }
+        self.set_len(last);
+    }
+}
+
+pub(crate) fn dynamic_vec_to_mut(c: &mut dyn ComponentVec) -> &mut DynamicVec {
+    c.to_any_mut()
+        .downcast_mut::<RwLock<DynamicVec>>()
+        .unwrap()
+        .get_mut()
+        .unwrap()
+}
+
+impl ComponentVec for RwLock<DynamicVec> {
+    fn to_any(&self) -> &dyn Any {
+        self
+    }
+    fn to_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn len(&mut self) -> usize {
+        self.get_mut().unwrap().len
+    }
+
+    fn swap_remove(&mut self, index: EntityId) {
+        self.get_mut().unwrap().swap_remove(index as usize);
+    }
+
+    fn clear(&mut self) {
+        self.get_mut().unwrap().set_len(0);
+    }
+
+    fn migrate(&mut self, entity_index: EntityId, other_component_vec: &mut dyn ComponentVec) {
+        let data = self.get_mut().unwrap();
+        dynamic_vec_to_mut(other_component_vec).push(data.row(entity_index as usize));
+        data.swap_remove(entity_index as usize);
+    }
+
+    fn append(&mut self, other: &mut dyn ComponentVec) {
+        let data = self.get_mut().unwrap();
+        let other = dynamic_vec_to_mut(other);
+        let start = other.len;
+        other.set_len(start + data.len);
+        let bytes = data.len * data.stride;
+        bytemuck::cast_slice_mut::<_, u8>(&mut other.blocks)
+            [start * other.stride..start * other.stride + bytes]
+            .copy_from_slice(&bytemuck::cast_slice::<_, u8>(&data.blocks)[..bytes]);
+        data.set_len(0);
+    }
+
+    fn new_same_type(&self) -> Box<dyn ComponentVec + Send + Sync> {
+        let data = self.read().unwrap();
+        Box::new(RwLock::new(DynamicVec {
+            size: data.size,
+            stride: data.stride,
+            len: 0,
+            blocks: Vec::new(),
+        }))
+    }
+
+    fn try_read_erased(&self) -> Option<ErasedColumn<'_>> {
+        let guard = self.try_read().ok()?;
+        Some(ErasedColumn {
+            ptr: guard.blocks.as_ptr() as *mut u8,
+            stride: guard.stride,
+            len: guard.len,
+            _guard: Box::new(guard),
+        })
+    }
+
+    fn try_write_erased(&self) -> Option<ErasedColumn<'_>> {
+        let mut guard = self.try_write().ok()?;
+        Some(ErasedColumn {
+            ptr: guard.blocks.as_mut_ptr() as *mut u8,
+            stride: guard.stride,
+            len: guard.len,
+            _guard: Box::new(guard),
+        })
+    }
 }
 
 /// Stores components for a component type
 pub(crate) struct ComponentStore {
-    pub(crate) type_id: TypeId,
+    pub(crate) id: ComponentId,
     pub data: Box<dyn ComponentVec + Send + Sync>,
 }
 
@@ -63,15 +304,34 @@ pub(crate) struct ComponentStore {
 impl ComponentStore {
     pub fn new<T: 'static + Send + Sync>() -> Self {
         Self {
-            type_id: TypeId::of::<T>(),
+            id: ComponentId::of::<T>(),
             data: Box::new(RwLock::new(Vec::<T>::new())),
         }
     }
 
+    /// Creates a ComponentStore holding a single component.
+    /// This is used to pass a component around without knowing its type.
+    pub fn from_value<T: 'static + Send + Sync>(t: T) -> Self {
+        Self {
+            id: ComponentId::of::<T>(),
+            data: Box::new(RwLock::new(vec![t])),
+        }
+    }
+
+    /// Creates a ComponentStore holding a single dynamic component copied from bytes.
+    pub(crate) fn from_bytes(id: ComponentId, layout: Layout, bytes: &[u8]) -> Self {
+        let mut data = DynamicVec::new(layout);
+        data.push(bytes);
+        Self {
+            id,
+            data: Box::new(RwLock::new(data)),
+        }
+    }
+
     /// Creates a new ComponentStore with the same internal storage type as self
     pub fn new_same_type(&self) -> Self {
         Self {
-            type_id: self.type_id,
+            id: self.id,
             data: self.data.new_same_type(),
         }
     }
@@ -0,0 +1,445 @@
+//! Replicating a `World` by sending only what changed.
+//!
+//! A `WorldDelta` is the difference between two `WorldSnapshot`s of the same world.
+//! Applying it to a world that matches the older snapshot makes that world match the newer one,
+//! so a server can keep the last snapshot each client acknowledged and send it a delta
+//! instead of a whole snapshot.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+use crate::snapshot::*;
+use crate::world::*;
+
+/// The changes that turn one `WorldSnapshot` into another.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WorldDelta {
+    /// The number of entity slots in the newer world.
+    pub entity_count: usize,
+    /// Every entity slot that changed, in index order.
+    pub entities: Vec<EntityDelta>,
+    /// Despawned slots in the order they will be reused.
+    pub free_entities: Vec<EntityId>,
+}
+
+/// A change to a single entity slot within a `WorldDelta`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntityDelta {
+    pub index: EntityId,
+    /// The slot's generation in the newer world.
+    pub generation: EntityId,
+    pub change: EntityChange,
+}
+
+/// How an entity slot changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EntityChange {
+    /// The slot does not hold a live entity.
+    Despawned,
+    /// A new entity with these components, keyed by their registered name, took the slot.
+    Spawned(BTreeMap<String, serde_json::Value>),
+    /// The entity is the same but some of its components were added, changed or removed.
+    Changed {
+        /// Components that were added or whose values changed.
+        set: BTreeMap<String, serde_json::Value>,
+        /// Names of components that were removed.
+        removed: Vec<String>,
+    },
+}
+
+impl WorldSnapshot {
+    /// Returns the changes that turn this snapshot into `newer`.
+    /// Slots are compared by generation and components by their serialized values.
+    pub fn diff(&self, newer: &WorldSnapshot) -> WorldDelta {
+        // Slots beyond the end of the older snapshot are treated as unused.
+        let unused = EntitySnapshot {
+            generation: 0,
+            components: None,
+        };
+
+        let mut entities = Vec::new();
+        for (index, after) in newer.entities.iter().enumerate() {
+            let before = self.entities.get(index).unwrap_or(&unused);
+            let change = match (&before.components, &after.components) {
+                (None, None) if before.generation == after.generation => continue,
+                (_, None) => EntityChange::Despawned,
+                (Some(before_components), Some(after_components))
+                    if before.generation == after.generation =>
+                {
+                    let set: BTreeMap<String, serde_json::Value> = after_components
+                        .iter()
+                        .filter(|(name, value)| before_components.get(*name) != Some(*value))
+                        .map(|(name, value)| (name.clone(), value.clone()))
+                        .collect();
+                    let removed: Vec<String> = before_components
+                        .keys()
+                        .filter(|name| !after_components.contains_key(*name))
+                        .cloned()
+                        .collect();
+                    if set.is_empty() && removed.is_empty() {
+                        continue;
+                    }
+                    EntityChange::Changed { set, removed }
+                }
+                (_, Some(after_components)) => EntityChange::Spawned(after_components.clone()),
+            };
+
+            entities.push(EntityDelta {
+                index: index as EntityId,
+                generation: after.generation,
+                change,
+            });
+        }
+
+        WorldDelta {
+            entity_count: newer.entities.len(),
+            entities,
+            free_entities: newer.free_entities.clone(),
+        }
+    }
+}
+
+/// A change from a `WorldDelta` that has been checked against the world and deserialized.
+enum PreparedChange {
+    Despawned,
+    Spawned(Vec<ComponentStore>),
+    Changed {
+        set: Vec<ComponentStore>,
+        removed: Vec<ComponentId>,
+    },
+}
+
+impl World {
+    /// Applies a delta computed from a snapshot of a world in the same state as this one.
+    /// If an error is returned the world is left unchanged.
+    pub fn apply_delta(&mut self, delta: &WorldDelta) -> Result<(), Error> {
+        // Check and deserialize everything up front so a failure leaves the world untouched.
+        let mut alive_after = HashMap::with_capacity(delta.entities.len());
+        let mut changes = Vec::with_capacity(delta.entities.len());
+        for entity_delta in delta.entities.iter() {
+            let index = entity_delta.index;
+            if index as usize >= delta.entity_count {
+                return Err(
+                    InvalidDelta::new(format!("entity slot {} is out of range", index)).into(),
+                );
+            }
+
+            let change = match &entity_delta.change {
+                EntityChange::Despawned => PreparedChange::Despawned,
+                EntityChange::Spawned(components) => {
+                    PreparedChange::Spawned(self.deserialize_components(components)?)
+                }
+                EntityChange::Changed { set, removed } => {
+                    let entity = Entity {
+                        index,
+                        generation: entity_delta.generation,
+                    };
+                    let entity_info = self.entity_info(entity).ok_or_else(|| {
+                        InvalidDelta::new(format!("entity {} is not in the world", entity))
+                    })?;
+                    let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+
+                    let mut removed_ids = Vec::with_capacity(removed.len());
+                    for name in removed.iter() {
+                        let id = self
+                            .registry
+                            .id_of(name)
+                            .ok_or_else(|| ComponentNotRegistered::new(name.as_str()))?;
+                        if !archetype.components.iter().any(|c| c.id == id) {
+                            return Err(InvalidDelta::new(format!(
+                                "entity {} does not have a [{}] component to remove",
+                                entity, name
+                            ))
+                            .into());
+                        }
+                        removed_ids.push(id);
+                    }
+
+                    PreparedChange::Changed {
+                        set: self.deserialize_components(set)?,
+                        removed: removed_ids,
+                    }
+                }
+            };
+
+            let alive = !matches!(change, PreparedChange::Despawned);
+            if alive_after.insert(index, alive).is_some() {
+                return Err(InvalidDelta::new(format!(
+                    "entity slot {} is changed more than once",
+                    index
+                ))
+                .into());
+            }
+            changes.push((entity_delta, change));
+        }
+
+        let mut free = HashSet::new();
+        for index in delta.free_entities.iter() {
+            // A repeated slot would be handed out to two entities.
+            if !free.insert(*index) {
+                return Err(InvalidDelta::new(format!(
+                    "free entity slot {} appears more than once",
+                    index
+                ))
+                .into());
+            }
+            let alive = alive_after.get(index).copied().unwrap_or_else(|| {
+                self.entities
+                    .get(*index as usize)
+                    .is_some_and(|entity_info| entity_info.alive)
+            });
+            if *index as usize >= delta.entity_count || alive {
+                return Err(InvalidDelta::new(format!(
+                    "free entity slot {} is not a despawned entity",
+                    index
+                ))
+                .into());
+            }
+        }
+
+        // Resize the entity slots to match the newer world.
+        for index in delta.entity_count..self.entities.len() {
+            if self.entities[index].alive {
+                self.take_stores(index as EntityId);
+            }
+        }
+        self.entities.truncate(delta.entity_count);
+        self.entities.resize(
+            delta.entity_count,
+            EntityInfo {
+                generation: 0,
+                location: EntityLocation {
+                    archetype_index: 0,
+                    index_in_archetype: 0,
+                },
+                alive: false,
+            },
+        );
+
+        for (entity_delta, change) in changes {
+            let index = entity_delta.index;
+            match change {
+                PreparedChange::Despawned => {
+                    if self.entities[index as usize].alive {
+                        self.take_stores(index);
+                    }
+                }
+                PreparedChange::Spawned(stores) => {
+                    if self.entities[index as usize].alive {
+                        self.take_stores(index);
+                    }
+                    self.spawn_stores(index, stores);
+                }
+                PreparedChange::Changed { set, removed } => {
+                    let mut stores = self.take_stores(index);
+                    stores
+                        .retain(|c| !removed.contains(&c.id) && !set.iter().any(|s| s.id == c.id));
+                    stores.extend(set);
+                    self.spawn_stores(index, stores);
+                }
+            }
+            self.entities[index as usize].generation = entity_delta.generation;
+        }
+
+        self.free_entities = delta.free_entities.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Health(u32);
+
+    fn registered_world() -> World {
+        let mut world = World::new();
+        world.register_serializable::<Position>("Position").unwrap();
+        world.register_serializable::<Health>("Health").unwrap();
+        world
+    }
+
+    /// Applies the delta between two snapshots to a world restored from the first
+    /// and checks it ends up matching the second.
+    fn assert_round_trip(before: &WorldSnapshot, after: &WorldSnapshot) {
+        let delta = before.diff(after);
+        let text = serde_json::to_string(&delta).unwrap();
+        let delta: WorldDelta = serde_json::from_str(&text).unwrap();
+
+        let mut world = registered_world();
+        world.restore(before).unwrap();
+        world.apply_delta(&delta).unwrap();
+        assert_eq!(&world.snapshot().unwrap(), after);
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let mut world = registered_world();
+        let a = world.spawn((Position { x: 1.0, y: 2.0 }, Health(10)));
+        let b = world.spawn((Position { x: 3.0, y: 4.0 },));
+        let c = world.spawn((Health(5),));
+        let unchanged = world.spawn((Position { x: 5.0, y: 6.0 },));
+        let before = world.snapshot().unwrap();
+
+        world.get_component_mut::<Position>(a).unwrap().x = 100.0;
+        world.remove_component::<Health>(a).unwrap();
+        world.add_component(b, Health(7)).unwrap();
+        world.despawn(c).unwrap();
+        world.spawn((Position { x: 7.0, y: 8.0 },));
+        world.spawn((Health(1),));
+        let after = world.snapshot().unwrap();
+
+        let delta = before.diff(&after);
+        assert!(delta
+            .entities
+            .iter()
+            .all(|entity_delta| entity_delta.index != unchanged.index));
+        assert_eq!(
+            delta.entities[0].change,
+            EntityChange::Changed {
+                set: BTreeMap::from([(
+                    "Position".to_string(),
+                    serde_json::json!({ "x": 100.0, "y": 2.0 })
+                )]),
+                removed: vec!["Health".to_string()],
+            }
+        );
+
+        assert_round_trip(&before, &after);
+        assert_round_trip(&after, &before);
+        assert_round_trip(&before, &before);
+        assert_round_trip(&registered_world().snapshot().unwrap(), &after);
+    }
+
+    #[test]
+    fn test_delta_rejects_mismatched_world() {
+        let mut world = registered_world();
+        let a = world.spawn((Position { x: 1.0, y: 2.0 }, Health(10)));
+        let before = world.snapshot().unwrap();
+        world.remove_component::<Health>(a).unwrap();
+        let delta = before.diff(&world.snapshot().unwrap());
+
+        // The component the delta removes is already gone.
+        let unchanged = world.snapshot().unwrap();
+        assert!(matches!(
+            world.apply_delta(&delta),
+            Err(Error::InvalidDelta(_))
+        ));
+        assert_eq!(world.snapshot().unwrap(), unchanged);
+
+        // The entity the delta changes has been replaced.
+        world.despawn(a).unwrap();
+        world.spawn((Position { x: 0.0, y: 0.0 }, Health(0)));
+        assert!(matches!(
+            world.apply_delta(&delta),
+            Err(Error::InvalidDelta(_))
+        ));
+
+        // The delta's free list would hand out the same slot twice.
+        let mut world = registered_world();
+        let a = world.spawn((Health(1),));
+        let before = world.snapshot().unwrap();
+        world.despawn(a).unwrap();
+        let mut delta = before.diff(&world.snapshot().unwrap());
+        delta.free_entities = vec![a.index, a.index];
+        world.restore(&before).unwrap();
+        assert!(matches!(
+            world.apply_delta(&delta),
+            Err(Error::InvalidDelta(_))
+        ));
+        assert_eq!(world.snapshot().unwrap(), before);
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Spawn(Option<f32>, Option<u32>),
+        Despawn(usize),
+        SetPosition(usize, f32),
+        AddHealth(usize, u32),
+        RemoveHealth(usize),
+    }
+
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (proptest::option::of(-1e6f32..1e6), any::<Option<u32>>())
+                .prop_map(|(p, h)| Op::Spawn(p, h)),
+            any::<usize>().prop_map(Op::Despawn),
+            (any::<usize>(), -1e6f32..1e6).prop_map(|(i, x)| Op::SetPosition(i, x)),
+            (any::<usize>(), any::<u32>()).prop_map(|(i, h)| Op::AddHealth(i, h)),
+            any::<usize>().prop_map(Op::RemoveHealth),
+        ]
+    }
+
+    fn run(world: &mut World, entities: &mut Vec<Entity>, ops: &[Op]) {
+        for op in ops.iter() {
+            let pick = |i: &usize| entities.get(i % entities.len().max(1)).copied();
+            match op {
+                Op::Spawn(p, h) => {
+                    let entity = world.spawn((Health(0),));
+                    world.remove_component::<Health>(entity).unwrap();
+                    if let Some(x) = p {
+                        world
+                            .add_component(entity, Position { x: *x, y: 0.0 })
+                            .unwrap();
+                    }
+                    if let Some(h) = h {
+                        world.add_component(entity, Health(*h)).unwrap();
+                    }
+                    entities.push(entity);
+                }
+                Op::Despawn(i) => {
+                    if let Some(entity) = pick(i) {
+                        let _ = world.despawn(entity);
+                    }
+                }
+                Op::SetPosition(i, x) => {
+                    if let Some(entity) = pick(i) {
+                        if let Ok(position) = world.get_component_mut::<Position>(entity) {
+                            position.x = *x;
+                        }
+                    }
+                }
+                Op::AddHealth(i, h) => {
+                    if let Some(entity) = pick(i) {
+                        let _ = world.add_component(entity, Health(*h));
+                    }
+                }
+                Op::RemoveHealth(i) => {
+                    if let Some(entity) = pick(i) {
+                        let _ = world.remove_component::<Health>(entity);
+                    }
+                }
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_delta_random_round_trip(
+            first in proptest::collection::vec(op_strategy(), 0..32),
+            second in proptest::collection::vec(op_strategy(), 0..32),
+        ) {
+            let mut world = registered_world();
+            let mut entities = Vec::new();
+            run(&mut world, &mut entities, &first);
+            let before = world.snapshot().unwrap();
+            run(&mut world, &mut entities, &second);
+            let after = world.snapshot().unwrap();
+
+            assert_round_trip(&before, &after);
+            assert_round_trip(&after, &before);
+        }
+    }
+}
@@ -0,0 +1,580 @@
+//! Components defined at runtime, for example by scripts or mods.
+//!
+//! A dynamic component is a block of plain bytes with a `Layout`, registered with
+//! `World::register_dynamic_component`. It is stored in its own column like any other component
+//! so an entity can have dynamic and Rust components side by side,
+//! and a `DynamicQuery` built by a `QueryBuilder` can combine both.
+
+use std::alloc::Layout;
+
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+use crate::world::*;
+
+impl World {
+    /// Registers a component defined at runtime as `layout.size()` bytes aligned to `layout.align()`.
+    /// Registering the same name and layout again returns the same id,
+    /// and an error is returned if the name is already registered to another component.
+    ///
+    /// # Panics
+    /// Panics if the layout is aligned to more than 16 bytes.
+    pub fn register_dynamic_component(
+        &mut self,
+        name: &str,
+        layout: Layout,
+    ) -> Result<ComponentId, ComponentNameConflict> {
+        self.registry.register_dynamic(name, layout)
+    }
+
+    /// Adds a dynamic component to an entity by copying its bytes.
+    /// If the component already exists its data will be replaced.
+    pub fn insert_dynamic(
+        &mut self,
+        entity: Entity,
+        id: ComponentId,
+        bytes: &[u8],
+    ) -> Result<(), Error> {
+        let entity_info = self
+            .entity_info(entity)
+            .ok_or_else(|| NoSuchEntity::new(entity))?;
+        let layout = self.dynamic_layout(id)?;
+        if bytes.len() != layout.size() {
+            return Err(ComponentSizeMismatch::new(
+                self.component_name(id),
+                layout.size(),
+                bytes.len(),
+            )
+            .into());
+        }
+
+        let archetype = &mut self.archetypes[entity_info.location.archetype_index as usize];
+        if let Some(c) = archetype.components.iter_mut().find(|c| c.id == id) {
+            dynamic_vec_to_mut(&mut *c.data)
+                .row_mut(entity_info.location.index_in_archetype as usize)
+                .copy_from_slice(bytes);
+        } else {
+            let mut stores = self.take_stores(entity.index);
+            stores.push(ComponentStore::from_bytes(id, layout, bytes));
+            self.spawn_stores(entity.index, stores);
+        }
+        Ok(())
+    }
+
+    /// Gets immutable access to the bytes of a dynamic component on an `Entity`.
+    /// An error is returned if the component is currently mutably borrowed.
+    pub fn get_dynamic(&self, entity: Entity, id: ComponentId) -> Result<DynamicRef<'_>, Error> {
+        let entity_info = self
+            .entity_info(entity)
+            .ok_or_else(|| NoSuchEntity::new(entity))?;
+        let size = self.dynamic_layout(id)?.size();
+        let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+        let c = archetype
+            .components
+            .iter()
+            .find(|c| c.id == id)
+            .ok_or_else(|| EntityMissingComponent::with_name(entity, self.component_name(id)))?;
+
+        let column = c
+            .data
+            .try_read_erased()
+            .ok_or_else(|| ComponentAlreadyBorrowed::with_name(self.component_name(id)))?;
+        Ok(DynamicRef {
+            column,
+            row: entity_info.location.index_in_archetype as usize,
+            size,
+        })
+    }
+
+    /// Gets mutable access to the bytes of a dynamic component on an `Entity`.
+    pub fn get_dynamic_mut(&mut self, entity: Entity, id: ComponentId) -> Result<&mut [u8], Error> {
+        let entity_info = self
+            .entity_info(entity)
+            .ok_or_else(|| NoSuchEntity::new(entity))?;
+        self.dynamic_layout(id)?;
+        let name = self.component_name(id);
+        let archetype = &mut self.archetypes[entity_info.location.archetype_index as usize];
+        let c = archetype
+            .components
+            .iter_mut()
+            .find(|c| c.id == id)
+            .ok_or_else(|| EntityMissingComponent::with_name(entity, name))?;
+        Ok(dynamic_vec_to_mut(&mut *c.data)
+            .row_mut(entity_info.location.index_in_archetype as usize))
+    }
+
+    /// Removes a component of any kind from an entity, dropping it.
+    pub fn remove_component_by_id(&mut self, entity: Entity, id: ComponentId) -> Result<(), Error> {
+        let entity_info = self
+            .entity_info(entity)
+            .ok_or_else(|| NoSuchEntity::new(entity))?;
+        let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+        if !archetype.components.iter().any(|c| c.id == id) {
+            return Err(EntityMissingComponent::with_name(entity, self.component_name(id)).into());
+        }
+
+        let mut stores = self.take_stores(entity.index);
+        stores.retain(|c| c.id != id);
+        self.spawn_stores(entity.index, stores);
+        Ok(())
+    }
+
+    /// Queries every entity that has all of the components, which may be dynamic or Rust types.
+    /// The components are only borrowed immutably.
+    /// Use a `QueryBuilder` to write to components or exclude entities.
+    pub fn query_dynamic(&self, ids: &[ComponentId]) -> Result<DynamicQuery<'_>, FetchError> {
+        ids.iter()
+            .fold(QueryBuilder::new(self), |builder, id| builder.read(*id))
+            .build()
+    }
+
+    /// The layout of a dynamic component.
+    fn dynamic_layout(&self, id: ComponentId) -> Result<Layout, ComponentNotRegistered> {
+        self.registry
+            .info(id)
+            .filter(|info| info.id().is_dynamic())
+            .map(|info| info.layout())
+            .ok_or_else(|| ComponentNotRegistered::new(self.component_name(id)))
+    }
+}
+
+/// The bytes of a dynamic component, borrowed from the world.
+pub struct DynamicRef<'world_borrow> {
+    column: ErasedColumn<'world_borrow>,
+    row: usize,
+    size: usize,
+}
+
+impl std::ops::Deref for DynamicRef<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        // Dynamic columns only hold initialized bytes and stay locked while `column` is held.
+        unsafe { std::slice::from_raw_parts(self.column.row_ptr(self.row), self.size) }
+    }
+}
+
+/// Builds a `DynamicQuery` from components chosen at runtime.
+///
+/// # Example
+/// ```
+/// # use kecs::component::ComponentId;
+/// # use kecs::dynamic::QueryBuilder;
+/// # use kecs::world::World;
+/// # let mut world = World::new();
+/// let mana = world.register_dynamic_component("Mana", std::alloc::Layout::new::<u32>()).unwrap();
+/// let entity = world.spawn((1.0f32,));
+/// world.insert_dynamic(entity, mana, &7u32.to_ne_bytes()).unwrap();
+///
+/// let mut query = QueryBuilder::new(&world)
+///     .read(ComponentId::of::<f32>())
+///     .write(mana)
+///     .without(ComponentId::of::<bool>())
+///     .build()
+///     .unwrap();
+/// for mut row in query.iter_mut() {
+///     let speed = *row.get::<f32>().unwrap();
+///     row.bytes_mut(mana).unwrap()[0] += speed as u8;
+/// }
+/// ```
+pub struct QueryBuilder<'world_borrow> {
+    world: &'world_borrow World,
+    /// Each requested component and whether it is written.
+    access: Vec<(ComponentId, bool)>,
+    without: Vec<ComponentId>,
+}
+
+impl<'world_borrow> QueryBuilder<'world_borrow> {
+    pub fn new(world: &'world_borrow World) -> Self {
+        Self {
+            world,
+            access: Vec::new(),
+            without: Vec::new(),
+        }
+    }
+
+    /// Requires the component and borrows it immutably.
+    pub fn read(mut self, id: ComponentId) -> Self {
+        self.access.push((id, false));
+        self
+    }
+
+    /// Requires the component and borrows it mutably.
+    pub fn write(mut self, id: ComponentId) -> Self {
+        self.access.push((id, true));
+        self
+    }
+
+    /// Skips entities that have the component.
+    pub fn without(mut self, id: ComponentId) -> Self {
+        self.without.push(id);
+        self
+    }
+
+    /// Borrows the requested components from every matching archetype.
+    /// Like a typed query, an error is returned if a component is already borrowed in a way
+    /// that conflicts with the request, including by an earlier request in the same query.
+    pub fn build(self) -> Result<DynamicQuery<'world_borrow>, FetchError> {
+        let world = self.world;
+        let mut sizes = Vec::with_capacity(self.access.len());
+        for (id, _) in self.access.iter() {
+            let info = world
+                .registry
+                .info(*id)
+                .ok_or_else(|| ComponentDoesNotExist::with_name(world.component_name(*id)))?;
+            sizes.push(info.layout().size());
+        }
+
+        let mut archetypes = Vec::new();
+        for (archetype_index, archetype) in world.archetypes.iter().enumerate() {
+            let excluded = archetype
+                .components
+                .iter()
+                .any(|c| self.without.contains(&c.id));
+            if archetype.entities.is_empty() || excluded {
+                continue;
+            }
+
+            let mut columns = Vec::with_capacity(self.access.len());
+            for (id, write) in self.access.iter() {
+                let Some(c) = archetype.components.iter().find(|c| c.id == *id) else {
+                    break;
+                };
+                let column = if *write {
+                    world.mark_column_changed(*id, archetype_index);
+                    c.data.try_write_erased()
+                } else {
+                    c.data.try_read_erased()
+                };
+                columns.push(column.ok_or_else(|| {
+                    ComponentAlreadyBorrowed::with_name(world.component_name(*id))
+                })?);
+            }
+            if columns.len() == self.access.len() {
+                archetypes.push((archetype_index, columns));
+            }
+        }
+
+        Ok(DynamicQuery {
+            world,
+            ids: self.access.iter().map(|(id, _)| *id).collect(),
+            writes: self.access.iter().map(|(_, write)| *write).collect(),
+            sizes,
+            archetypes,
+        })
+    }
+}
+
+/// A query over components chosen at runtime.
+/// The columns it uses stay borrowed until it is dropped.
+pub struct DynamicQuery<'world_borrow> {
+    world: &'world_borrow World,
+    ids: Vec<ComponentId>,
+    writes: Vec<bool>,
+    sizes: Vec<usize>,
+    /// Each matching archetype and its columns in the order of `ids`.
+    archetypes: Vec<(usize, Vec<ErasedColumn<'world_borrow>>)>,
+}
+
+impl<'world_borrow> DynamicQuery<'world_borrow> {
+    /// Iterates over every matching entity with read access to its components.
+    pub fn iter(&self) -> impl Iterator<Item = DynamicRow<'_>> {
+        self.rows(false)
+    }
+
+    /// Iterates over every matching entity with write access to the components requested with `write`.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = DynamicRow<'_>> {
+        self.rows(true)
+    }
+
+    fn rows(&self, mutable: bool) -> impl Iterator<Item = DynamicRow<'_>> {
+        self.archetypes
+            .iter()
+            .flat_map(move |(archetype_index, columns)| {
+                let archetype = &self.world.archetypes[*archetype_index];
+                archetype
+                    .entities
+                    .iter()
+                    .enumerate()
+                    .map(move |(row, index)| DynamicRow {
+                        entity: Entity {
+                            index: *index,
+                            generation: self.world.entities[*index as usize].generation,
+                        },
+                        row,
+                        mutable,
+                        ids: &self.ids,
+                        writes: &self.writes,
+                        sizes: &self.sizes,
+                        columns,
+                    })
+            })
+    }
+}
+
+/// The components of a single entity within a `DynamicQuery`.
+pub struct DynamicRow<'a> {
+    entity: Entity,
+    row: usize,
+    /// True if the row came from `iter_mut`.
+    mutable: bool,
+    ids: &'a [ComponentId],
+    writes: &'a [bool],
+    sizes: &'a [usize],
+    columns: &'a [ErasedColumn<'a>],
+}
+
+impl DynamicRow<'_> {
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    fn position(&self, id: ComponentId) -> Option<usize> {
+        self.ids.iter().position(|i| *i == id)
+    }
+
+    /// Like `position` but only for components this row may write to.
+    fn write_position(&self, id: ComponentId) -> Option<usize> {
+        self.position(id)
+            .filter(|i| self.mutable && self.writes[*i])
+    }
+
+    /// Returns a pointer to one of the queried components, which may be dynamic or a Rust type.
+    pub fn ptr(&self, id: ComponentId) -> Option<*const u8> {
+        let i = self.position(id)?;
+        Some(self.columns[i].row_ptr(self.row) as *const u8)
+    }
+
+    /// Returns a pointer that may be written through to one of the components requested with `write`.
+    /// Returns `None` if the row did not come from `iter_mut`.
+    pub fn ptr_mut(&mut self, id: ComponentId) -> Option<*mut u8> {
+        let i = self.write_position(id)?;
+        Some(self.columns[i].row_ptr(self.row))
+    }
+
+    /// Returns the bytes of one of the queried dynamic components.
+    /// Rust components may contain uninitialized padding so they are not available as bytes,
+    /// use `get` or `ptr` instead.
+    pub fn bytes(&self, id: ComponentId) -> Option<&[u8]> {
+        let i = self.position(id).filter(|_| id.is_dynamic())?;
+        // Dynamic columns only hold initialized bytes and stay locked while the query is held.
+        Some(unsafe {
+            std::slice::from_raw_parts(self.columns[i].row_ptr(self.row), self.sizes[i])
+        })
+    }
+
+    /// Returns the bytes of one of the dynamic components requested with `write`.
+    pub fn bytes_mut(&mut self, id: ComponentId) -> Option<&mut [u8]> {
+        let i = self.write_position(id).filter(|_| id.is_dynamic())?;
+        // The column is locked for writing and each row is only handed out once by `iter_mut`.
+        Some(unsafe {
+            std::slice::from_raw_parts_mut(self.columns[i].row_ptr(self.row), self.sizes[i])
+        })
+    }
+
+    /// Returns one of the queried Rust components.
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        let i = self.position(ComponentId::of::<T>())?;
+        // The column is a `Vec<T>` because it matched `T`'s id, and it stays locked while the query is held.
+        Some(unsafe { &*(self.columns[i].row_ptr(self.row) as *const T) })
+    }
+
+    /// Returns one of the Rust components requested with `write`.
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        let i = self.write_position(ComponentId::of::<T>())?;
+        // As for `get`, and the column is locked for writing.
+        Some(unsafe { &mut *(self.columns[i].row_ptr(self.row) as *mut T) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[test]
+    fn test_dynamic_components() {
+        let mut world = World::new();
+        let mana = world
+            .register_dynamic_component("Mana", Layout::new::<u32>())
+            .unwrap();
+        let tag = world
+            .register_dynamic_component("Tag", Layout::new::<()>())
+            .unwrap();
+        assert_eq!(
+            world
+                .register_dynamic_component("Mana", Layout::new::<u32>())
+                .unwrap(),
+            mana
+        );
+        assert_eq!(world.registry().id_of("Mana"), Some(mana));
+        assert_eq!(mana.type_id(), None);
+
+        let a = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let b = world.spawn((Position { x: 3.0, y: 4.0 }, true));
+        world.insert_dynamic(a, mana, &10u32.to_ne_bytes()).unwrap();
+        world.insert_dynamic(b, mana, &20u32.to_ne_bytes()).unwrap();
+        world.insert_dynamic(b, tag, &[]).unwrap();
+
+        assert_eq!(*world.get_dynamic(a, mana).unwrap(), 10u32.to_ne_bytes());
+        world
+            .get_dynamic_mut(a, mana)
+            .unwrap()
+            .copy_from_slice(&15u32.to_ne_bytes());
+        world.insert_dynamic(b, mana, &25u32.to_ne_bytes()).unwrap();
+        assert_eq!(*world.get_dynamic(a, mana).unwrap(), 15u32.to_ne_bytes());
+        assert_eq!(*world.get_dynamic(b, mana).unwrap(), 25u32.to_ne_bytes());
+        assert_eq!(
+            *world.get_component_mut::<Position>(b).unwrap(),
+            Position { x: 3.0, y: 4.0 }
+        );
+
+        assert!(matches!(
+            world.insert_dynamic(a, mana, &[1]),
+            Err(Error::ComponentSizeMismatch(_))
+        ));
+        assert!(matches!(
+            world.insert_dynamic(a, ComponentId::of::<Position>(), &[]),
+            Err(Error::ComponentNotRegistered(_))
+        ));
+        assert!(matches!(
+            world.get_dynamic(a, tag),
+            Err(Error::EntityMissingComponent(_))
+        ));
+
+        world.remove_component_by_id(b, mana).unwrap();
+        assert!(world.get_dynamic(b, mana).is_err());
+        assert!(world.get_dynamic(b, tag).is_ok());
+        world.despawn(a).unwrap();
+        assert!(matches!(
+            world.get_dynamic(a, mana),
+            Err(Error::NoSuchEntity(_))
+        ));
+    }
+
+    #[test]
+    fn test_dynamic_query() {
+        let mut world = World::new();
+        let mana = world
+            .register_dynamic_component("Mana", Layout::new::<u64>())
+            .unwrap();
+        let mut expected = Vec::new();
+        for i in 0..10u64 {
+            let entity = world.spawn((Position {
+                x: i as f32,
+                y: 0.0,
+            },));
+            if i % 2 == 0 {
+                world
+                    .insert_dynamic(entity, mana, &i.to_ne_bytes())
+                    .unwrap();
+                expected.push((entity, i));
+            }
+        }
+        world.spawn((true,));
+
+        let query = world
+            .query_dynamic(&[ComponentId::of::<Position>(), mana])
+            .unwrap();
+        let mut found: Vec<(Entity, u64)> = query
+            .iter()
+            .map(|row| {
+                let bytes = row.bytes(mana).unwrap();
+                let value = u64::from_ne_bytes(bytes.try_into().unwrap());
+                assert_eq!(row.get::<Position>().unwrap().x, value as f32);
+                assert!(row.bytes(ComponentId::of::<Position>()).is_none());
+                assert_eq!(row.ptr(mana).unwrap() as usize % 8, 0);
+                (row.entity(), value)
+            })
+            .collect();
+        found.sort_by_key(|(_, value)| *value);
+        assert_eq!(found, expected);
+
+        // The query holds a read borrow on the columns it matched.
+        assert!(world.query::<(&mut Position,)>().is_err());
+        assert!(world.get_dynamic(expected[0].0, mana).is_ok());
+    }
+
+    #[test]
+    fn test_query_builder() {
+        let mut world = World::new();
+        let mana = world
+            .register_dynamic_component("Mana", Layout::new::<u32>())
+            .unwrap();
+        let frozen = world
+            .register_dynamic_component("Frozen", Layout::new::<()>())
+            .unwrap();
+        let position = ComponentId::of::<Position>();
+
+        let a = world.spawn((Position { x: 1.0, y: 0.0 },));
+        let b = world.spawn((Position { x: 2.0, y: 0.0 },));
+        let c = world.spawn((Position { x: 3.0, y: 0.0 },));
+        for entity in [a, b, c] {
+            world
+                .insert_dynamic(entity, mana, &5u32.to_ne_bytes())
+                .unwrap();
+        }
+        world.insert_dynamic(c, frozen, &[]).unwrap();
+
+        {
+            let mut query = QueryBuilder::new(&world)
+                .write(position)
+                .write(mana)
+                .without(frozen)
+                .build()
+                .unwrap();
+
+            // The columns are locked for writing until the query is dropped.
+            assert!(world.query::<(&Position,)>().is_err());
+            assert!(world.get_dynamic(a, mana).is_err());
+
+            for row in query.iter() {
+                assert!(row.bytes(mana).is_some());
+            }
+            let mut count = 0;
+            for mut row in query.iter_mut() {
+                let bytes = row.bytes_mut(mana).unwrap();
+                let value = u32::from_ne_bytes((&*bytes).try_into().unwrap());
+                bytes.copy_from_slice(&(value * 2).to_ne_bytes());
+                row.get_mut::<Position>().unwrap().y = 1.0;
+                count += 1;
+            }
+            assert_eq!(count, 2);
+        }
+
+        assert_eq!(*world.get_dynamic(a, mana).unwrap(), 10u32.to_ne_bytes());
+        assert_eq!(*world.get_dynamic(c, mana).unwrap(), 5u32.to_ne_bytes());
+        assert_eq!(world.get_component_mut::<Position>(b).unwrap().y, 1.0);
+        assert_eq!(world.get_component_mut::<Position>(c).unwrap().y, 0.0);
+
+        // Rows from `iter` and components only requested with `read` cannot be written.
+        let mut query = QueryBuilder::new(&world)
+            .read(position)
+            .write(mana)
+            .build()
+            .unwrap();
+        assert!(query.iter().next().unwrap().ptr_mut(mana).is_none());
+        assert!(query
+            .iter_mut()
+            .next()
+            .unwrap()
+            .get_mut::<Position>()
+            .is_none());
+        drop(query);
+
+        // The same borrow rules as typed queries apply within a single query.
+        assert!(QueryBuilder::new(&world)
+            .read(mana)
+            .read(mana)
+            .build()
+            .is_ok());
+        assert!(matches!(
+            QueryBuilder::new(&world).write(mana).read(mana).build(),
+            Err(FetchError::ComponentAlreadyBorrowed(_))
+        ));
+    }
+}
@@ -1,5 +1,33 @@
 // This can be used to easily change the size of an EntityId.
+// The `entity-id-64` feature widens it for worlds that churn through more entities.
+#[cfg(not(feature = "entity-id-64"))]
 pub(crate) type EntityId = u32;
+#[cfg(feature = "entity-id-64")]
+pub(crate) type EntityId = u64;
+
+/// The packed representation of an `Entity` returned by `Entity::to_bits`.
+/// It is twice as wide as an `EntityId`.
+#[cfg(not(feature = "entity-id-64"))]
+pub type EntityBits = u64;
+#[cfg(feature = "entity-id-64")]
+pub type EntityBits = u128;
+
+/// The generation given to a slot that has been retired.
+/// No live entity ever has this generation.
+pub(crate) const RETIRED_GENERATION: EntityId = EntityId::MAX;
+
+/// What the world does when an entity slot has been reused so often
+/// that its generation cannot be incremented any further.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GenerationOverflow {
+    /// The slot is never handed out again, so stale handles can never alias a new entity.
+    /// This leaks one slot per exhausted generation.
+    #[default]
+    Retire,
+    /// The generation wraps back to zero and the slot is reused.
+    /// A handle kept alive across the wrap may alias a newer entity.
+    Wrap,
+}
 
 /// An entity's location within archetype list
 #[derive(Debug, Clone, Copy)]
@@ -15,6 +43,8 @@ pub struct EntityLocation {
 pub(crate) struct EntityInfo {
     pub(crate) generation: EntityId,
     pub(crate) location: EntityLocation,
+    /// False while the slot is on the free list or retired.
+    pub(crate) alive: bool,
 }
 
 /// A handle to an entity within the world.
@@ -24,16 +54,121 @@ pub struct Entity {
     pub(crate) generation: EntityId,
 }
 
+impl Entity {
+    /// A handle that never refers to a live entity.
+    /// Useful for initializing `Entity` fields before the real entity is known.
+    /// The world never allocates this index.
+    pub const PLACEHOLDER: Entity = Entity {
+        index: EntityId::MAX,
+        generation: 0,
+    };
+
+    /// The entity's slot in the world.
+    /// Slots are reused after an entity is despawned.
+    pub fn index(self) -> EntityId {
+        self.index
+    }
+
+    /// How many times the entity's slot has been reused.
+    pub fn generation(self) -> EntityId {
+        self.generation
+    }
+
+    /// Packs the entity into a single integer, generation in the high half and index in the low half.
+    /// The result is stable and can be stored or sent over the network.
+    pub fn to_bits(self) -> EntityBits {
+        ((self.generation as EntityBits) << EntityId::BITS) | self.index as EntityBits
+    }
+
+    /// Reconstructs an entity from `to_bits`.
+    /// Returns `None` if the bits can never describe a live entity.
+    pub fn from_bits(bits: EntityBits) -> Option<Entity> {
+        let generation = (bits >> EntityId::BITS) as EntityId;
+        if generation == RETIRED_GENERATION {
+            None
+        } else {
+            Some(Entity {
+                index: bits as EntityId,
+                generation,
+            })
+        }
+    }
+}
+
+/// Implemented by components that hold `Entity` handles so the handles can be updated
+/// when the entities they refer to are recreated under new handles, for example when a scene is loaded.
+pub trait MapEntities {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity);
+}
+
+impl MapEntities for Entity {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        *self = map(*self);
+    }
+}
+
+// Human-readable formats store entities as their `Display` text ("12v3") so they are easy to author
+// and do not depend on `EntityBits` fitting in the format's integers.
+// Other formats store the packed bits.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Entity {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serde::Serialize::serialize(&self.to_bits(), serializer)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Entity {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entity = if deserializer.is_human_readable() {
+            let text = <std::borrow::Cow<str> as serde::Deserialize>::deserialize(deserializer)?;
+            text.split_once('v').and_then(|(index, generation)| {
+                Entity::from_bits(
+                    ((generation.parse::<EntityId>().ok()? as EntityBits) << EntityId::BITS)
+                        | index.parse::<EntityId>().ok()? as EntityBits,
+                )
+            })
+        } else {
+            Entity::from_bits(<EntityBits as serde::Deserialize>::deserialize(
+                deserializer,
+            )?)
+        };
+        entity.ok_or_else(|| serde::de::Error::custom("the value does not describe a valid entity"))
+    }
+}
+
+impl std::fmt::Display for Entity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
 /// This entity has been despawned so operations can no longer
 /// be performed on it.
 #[derive(Debug)]
-pub struct NoSuchEntity;
+pub struct NoSuchEntity(Entity);
+
+impl NoSuchEntity {
+    pub fn new(entity: Entity) -> Self {
+        Self(entity)
+    }
+
+    /// The entity the operation was attempted on.
+    pub fn entity(&self) -> Entity {
+        self.0
+    }
+}
 
 impl std::fmt::Display for NoSuchEntity {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "The entity no longer exists so the operation cannot be performed"
+            "Entity {} no longer exists so the operation cannot be performed",
+            self.0
         )
     }
 }
@@ -41,11 +176,21 @@ impl std::fmt::Display for NoSuchEntity {
 impl std::error::Error for NoSuchEntity {}
 
 #[derive(Debug)]
-pub struct EntityMissingComponent(EntityId, &'static str);
+pub struct EntityMissingComponent(Entity, std::borrow::Cow<'static, str>);
 
 impl EntityMissingComponent {
-    pub fn new<T>(entity_id: EntityId) -> Self {
-        Self(entity_id, std::any::type_name::<T>())
+    pub fn new<T>(entity: Entity) -> Self {
+        Self(entity, std::borrow::Cow::Borrowed(std::any::type_name::<T>()))
+    }
+
+    /// Creates the error for a component known only by its registered name.
+    pub fn with_name(entity: Entity, name: impl Into<std::borrow::Cow<'static, str>>) -> Self {
+        Self(entity, name.into())
+    }
+
+    /// The entity the component was requested from.
+    pub fn entity(&self) -> Entity {
+        self.0
     }
 }
 
@@ -53,7 +198,7 @@ impl std::fmt::Display for EntityMissingComponent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Entity {:?} does not have a [{}] component",
+            "Entity {} does not have a [{}] component",
             self.0, self.1
         )
     }
@@ -67,3 +212,66 @@ pub enum ComponentError {
     NoSuchEntity(NoSuchEntity),
 }
 
+impl std::fmt::Display for ComponentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EntityMissingComponent(e) => e.fmt(f),
+            Self::NoSuchEntity(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ComponentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::EntityMissingComponent(e) => e.source(),
+            Self::NoSuchEntity(e) => e.source(),
+        }
+    }
+}
+
+impl From<EntityMissingComponent> for ComponentError {
+    fn from(e: EntityMissingComponent) -> Self {
+        Self::EntityMissingComponent(e)
+    }
+}
+
+impl From<NoSuchEntity> for ComponentError {
+    fn from(e: NoSuchEntity) -> Self {
+        Self::NoSuchEntity(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entity_bits_round_trip() {
+        let entity = Entity {
+            index: 12,
+            generation: 3,
+        };
+        assert_eq!(Entity::from_bits(entity.to_bits()), Some(entity));
+        assert_eq!(entity.to_bits(), (3 << EntityId::BITS) | 12);
+        assert_eq!(
+            Entity::from_bits(Entity::PLACEHOLDER.to_bits()),
+            Some(Entity::PLACEHOLDER)
+        );
+        assert_eq!(
+            Entity::from_bits((RETIRED_GENERATION as EntityBits) << EntityId::BITS),
+            None
+        );
+    }
+
+    #[test]
+    fn test_entity_display() {
+        let entity = Entity {
+            index: 12,
+            generation: 3,
+        };
+        assert_eq!(entity.to_string(), "12v3");
+        assert_eq!(entity.index(), 12);
+        assert_eq!(entity.generation(), 3);
+    }
+}
@@ -1,16 +1,266 @@
+use crate::entity::*;
 
+/// Any error returned by an operation on the `World`.
+/// Every more specific error type converts into this so `?` can be used throughout.
+/// Each variant displays the error it wraps, so its `source` is that error's source rather than the error itself
+/// and the message is not repeated when the chain is printed.
+#[derive(Debug)]
+pub enum Error {
+    NoSuchEntity(NoSuchEntity),
+    EntityMissingComponent(EntityMissingComponent),
+    ComponentAlreadyBorrowed(ComponentAlreadyBorrowed),
+    ComponentDoesNotExist(ComponentDoesNotExist),
+    MultipleComponentsExist(MultipleComponentsExist),
+    ComponentNotRegistered(ComponentNotRegistered),
+    InvalidSnapshot(InvalidSnapshot),
+    InvalidDelta(InvalidDelta),
+    ComponentSizeMismatch(ComponentSizeMismatch),
+    HierarchyCycle(HierarchyCycle),
+    NoSuchIndex(NoSuchIndex),
+    NotCloneable(NotCloneable),
+    InvalidBundle(InvalidBundle),
+    ComponentNameConflict(ComponentNameConflict),
+    ScheduleCycle(ScheduleCycle),
+    /// Saved data could not be written or read back.
+    #[cfg(feature = "serde")]
+    Serialization(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoSuchEntity(e) => e.fmt(f),
+            Self::EntityMissingComponent(e) => e.fmt(f),
+            Self::ComponentAlreadyBorrowed(e) => e.fmt(f),
+            Self::ComponentDoesNotExist(e) => e.fmt(f),
+            Self::MultipleComponentsExist(e) => e.fmt(f),
+            Self::ComponentNotRegistered(e) => e.fmt(f),
+            Self::InvalidSnapshot(e) => e.fmt(f),
+            Self::InvalidDelta(e) => e.fmt(f),
+            Self::ComponentSizeMismatch(e) => e.fmt(f),
+            Self::HierarchyCycle(e) => e.fmt(f),
+            Self::NoSuchIndex(e) => e.fmt(f),
+            Self::NotCloneable(e) => e.fmt(f),
+            Self::InvalidBundle(e) => e.fmt(f),
+            Self::ComponentNameConflict(e) => e.fmt(f),
+            Self::ScheduleCycle(e) => e.fmt(f),
+            #[cfg(feature = "serde")]
+            Self::Serialization(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::NoSuchEntity(e) => e.source(),
+            Self::EntityMissingComponent(e) => e.source(),
+            Self::ComponentAlreadyBorrowed(e) => e.source(),
+            Self::ComponentDoesNotExist(e) => e.source(),
+            Self::MultipleComponentsExist(e) => e.source(),
+            Self::ComponentNotRegistered(e) => e.source(),
+            Self::InvalidSnapshot(e) => e.source(),
+            Self::InvalidDelta(e) => e.source(),
+            Self::ComponentSizeMismatch(e) => e.source(),
+            Self::HierarchyCycle(e) => e.source(),
+            Self::NoSuchIndex(e) => e.source(),
+            Self::NotCloneable(e) => e.source(),
+            Self::InvalidBundle(e) => e.source(),
+            Self::ComponentNameConflict(e) => e.source(),
+            Self::ScheduleCycle(e) => e.source(),
+            #[cfg(feature = "serde")]
+            Self::Serialization(e) => e.source(),
+        }
+    }
+}
+
+impl From<NoSuchEntity> for Error {
+    fn from(e: NoSuchEntity) -> Self {
+        Self::NoSuchEntity(e)
+    }
+}
+
+impl From<EntityMissingComponent> for Error {
+    fn from(e: EntityMissingComponent) -> Self {
+        Self::EntityMissingComponent(e)
+    }
+}
+
+impl From<ComponentAlreadyBorrowed> for Error {
+    fn from(e: ComponentAlreadyBorrowed) -> Self {
+        Self::ComponentAlreadyBorrowed(e)
+    }
+}
+
+impl From<ComponentDoesNotExist> for Error {
+    fn from(e: ComponentDoesNotExist) -> Self {
+        Self::ComponentDoesNotExist(e)
+    }
+}
+
+impl From<MultipleComponentsExist> for Error {
+    fn from(e: MultipleComponentsExist) -> Self {
+        Self::MultipleComponentsExist(e)
+    }
+}
+
+impl From<ComponentNotRegistered> for Error {
+    fn from(e: ComponentNotRegistered) -> Self {
+        Self::ComponentNotRegistered(e)
+    }
+}
+
+impl From<InvalidSnapshot> for Error {
+    fn from(e: InvalidSnapshot) -> Self {
+        Self::InvalidSnapshot(e)
+    }
+}
+
+impl From<InvalidDelta> for Error {
+    fn from(e: InvalidDelta) -> Self {
+        Self::InvalidDelta(e)
+    }
+}
+
+impl From<ComponentSizeMismatch> for Error {
+    fn from(e: ComponentSizeMismatch) -> Self {
+        Self::ComponentSizeMismatch(e)
+    }
+}
+
+impl From<HierarchyCycle> for Error {
+    fn from(e: HierarchyCycle) -> Self {
+        Self::HierarchyCycle(e)
+    }
+}
+
+impl From<NoSuchIndex> for Error {
+    fn from(e: NoSuchIndex) -> Self {
+        Self::NoSuchIndex(e)
+    }
+}
+
+impl From<NotCloneable> for Error {
+    fn from(e: NotCloneable) -> Self {
+        Self::NotCloneable(e)
+    }
+}
+
+impl From<InvalidBundle> for Error {
+    fn from(e: InvalidBundle) -> Self {
+        Self::InvalidBundle(e)
+    }
+}
+
+impl From<ComponentNameConflict> for Error {
+    fn from(e: ComponentNameConflict) -> Self {
+        Self::ComponentNameConflict(e)
+    }
+}
+
+impl From<ScheduleCycle> for Error {
+    fn from(e: ScheduleCycle) -> Self {
+        Self::ScheduleCycle(e)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ron::Error> for Error {
+    fn from(e: ron::Error) -> Self {
+        Self::Serialization(Box::new(e))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<ron::error::SpannedError> for Error {
+    fn from(e: ron::error::SpannedError) -> Self {
+        Self::Serialization(Box::new(e))
+    }
+}
+
+impl From<ComponentError> for Error {
+    fn from(e: ComponentError) -> Self {
+        match e {
+            ComponentError::EntityMissingComponent(e) => Self::EntityMissingComponent(e),
+            ComponentError::NoSuchEntity(e) => Self::NoSuchEntity(e),
+        }
+    }
+}
+
+impl From<FetchError> for Error {
+    fn from(e: FetchError) -> Self {
+        match e {
+            FetchError::ComponentAlreadyBorrowed(e) => Self::ComponentAlreadyBorrowed(e),
+            FetchError::ComponentDoesNotExist(e) => Self::ComponentDoesNotExist(e),
+            FetchError::MultipleComponentsExist(e) => Self::MultipleComponentsExist(e),
+        }
+    }
+}
+
+/// Displays the error it wraps, and like `Error` its `source` is that error's source.
 #[derive(Debug)]
 pub enum FetchError {
     ComponentAlreadyBorrowed(ComponentAlreadyBorrowed),
     ComponentDoesNotExist(ComponentDoesNotExist),
+    MultipleComponentsExist(MultipleComponentsExist),
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ComponentAlreadyBorrowed(e) => e.fmt(f),
+            Self::ComponentDoesNotExist(e) => e.fmt(f),
+            Self::MultipleComponentsExist(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ComponentAlreadyBorrowed(e) => e.source(),
+            Self::ComponentDoesNotExist(e) => e.source(),
+            Self::MultipleComponentsExist(e) => e.source(),
+        }
+    }
+}
+
+impl From<ComponentAlreadyBorrowed> for FetchError {
+    fn from(e: ComponentAlreadyBorrowed) -> Self {
+        Self::ComponentAlreadyBorrowed(e)
+    }
+}
+
+impl From<ComponentDoesNotExist> for FetchError {
+    fn from(e: ComponentDoesNotExist) -> Self {
+        Self::ComponentDoesNotExist(e)
+    }
+}
+
+impl From<MultipleComponentsExist> for FetchError {
+    fn from(e: MultipleComponentsExist) -> Self {
+        Self::MultipleComponentsExist(e)
+    }
 }
 
 #[derive(Debug)]
-pub struct ComponentAlreadyBorrowed(&'static str);
+pub struct ComponentAlreadyBorrowed(std::borrow::Cow<'static, str>);
 
 impl ComponentAlreadyBorrowed {
     pub fn new<T>() -> Self {
-        Self(std::any::type_name::<T>())
+        Self(std::borrow::Cow::Borrowed(std::any::type_name::<T>()))
+    }
+
+    /// Creates the error for a component known only by its registered name.
+    pub fn with_name(name: impl Into<std::borrow::Cow<'static, str>>) -> Self {
+        Self(name.into())
     }
 }
 
@@ -23,11 +273,16 @@ impl std::fmt::Display for ComponentAlreadyBorrowed {
 impl std::error::Error for ComponentAlreadyBorrowed {}
 
 #[derive(Debug)]
-pub struct ComponentDoesNotExist(&'static str);
+pub struct ComponentDoesNotExist(std::borrow::Cow<'static, str>);
 
 impl ComponentDoesNotExist {
     pub fn new<T>() -> Self {
-        Self(std::any::type_name::<T>())
+        Self(std::borrow::Cow::Borrowed(std::any::type_name::<T>()))
+    }
+
+    /// Creates the error for a component known only by its registered name.
+    pub fn with_name(name: impl Into<std::borrow::Cow<'static, str>>) -> Self {
+        Self(name.into())
     }
 }
 
@@ -38,3 +293,284 @@ impl std::fmt::Display for ComponentDoesNotExist {
 }
 
 impl std::error::Error for ComponentDoesNotExist {}
+
+/// A single component was requested but more than one entity has it.
+#[derive(Debug)]
+pub struct MultipleComponentsExist(std::borrow::Cow<'static, str>);
+
+impl MultipleComponentsExist {
+    pub fn new<T>() -> Self {
+        Self(std::borrow::Cow::Borrowed(std::any::type_name::<T>()))
+    }
+
+    /// Creates the error for a component known only by its registered name.
+    pub fn with_name(name: impl Into<std::borrow::Cow<'static, str>>) -> Self {
+        Self(name.into())
+    }
+}
+
+impl std::fmt::Display for MultipleComponentsExist {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] exists on more than one entity", self.0)
+    }
+}
+
+impl std::error::Error for MultipleComponentsExist {}
+
+/// A component type or name was used in an operation that requires it to be registered.
+#[derive(Debug)]
+pub struct ComponentNotRegistered(String);
+
+impl ComponentNotRegistered {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+}
+
+impl std::fmt::Display for ComponentNotRegistered {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] has not been registered with the world", self.0)
+    }
+}
+
+impl std::error::Error for ComponentNotRegistered {}
+
+/// Saved world data is corrupt or does not fit the world it is being loaded into.
+#[derive(Debug)]
+pub struct InvalidSnapshot(String);
+
+impl InvalidSnapshot {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self(reason.into())
+    }
+}
+
+impl std::fmt::Display for InvalidSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The snapshot cannot be restored: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidSnapshot {}
+
+/// A delta was computed against a different world state than the one it is being applied to.
+#[derive(Debug)]
+pub struct InvalidDelta(String);
+
+impl InvalidDelta {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self(reason.into())
+    }
+}
+
+impl std::fmt::Display for InvalidDelta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The delta cannot be applied: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidDelta {}
+
+/// The bytes given for a dynamic component do not match the size it was registered with.
+#[derive(Debug)]
+pub struct ComponentSizeMismatch {
+    name: String,
+    expected: usize,
+    found: usize,
+}
+
+impl ComponentSizeMismatch {
+    pub fn new(name: impl Into<String>, expected: usize, found: usize) -> Self {
+        Self {
+            name: name.into(),
+            expected,
+            found,
+        }
+    }
+}
+
+impl std::fmt::Display for ComponentSizeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] components are {} bytes but {} bytes were given",
+            self.name, self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for ComponentSizeMismatch {}
+
+/// An entity cannot be made the parent of itself or of one of its ancestors.
+#[derive(Debug)]
+pub struct HierarchyCycle {
+    child: Entity,
+    parent: Entity,
+}
+
+impl HierarchyCycle {
+    pub fn new(child: Entity, parent: Entity) -> Self {
+        Self { child, parent }
+    }
+}
+
+impl std::fmt::Display for HierarchyCycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Making entity {} the parent of entity {} would create a cycle",
+            self.parent, self.child
+        )
+    }
+}
+
+impl std::error::Error for HierarchyCycle {}
+
+/// A lookup was made by a component type that has not had an index created with `World::create_index`.
+#[derive(Debug)]
+pub struct NoSuchIndex(String);
+
+impl NoSuchIndex {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+}
+
+impl std::fmt::Display for NoSuchIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No index has been created for [{}] components", self.0)
+    }
+}
+
+impl std::error::Error for NoSuchIndex {}
+
+/// An entity could not be cloned because some of its component types were not registered with `register_clone`.
+#[derive(Debug)]
+pub struct NotCloneable(Vec<String>);
+
+impl NotCloneable {
+    pub fn new(names: Vec<String>) -> Self {
+        Self(names)
+    }
+
+    /// The names of the component types that cannot be cloned.
+    pub fn names(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for NotCloneable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{}] cannot be cloned because they were not registered with `register_clone`",
+            self.0.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for NotCloneable {}
+
+/// A bundle cannot be spawned because of the component types it contains.
+#[derive(Debug)]
+pub struct InvalidBundle(String);
+
+impl InvalidBundle {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self(reason.into())
+    }
+}
+
+impl std::fmt::Display for InvalidBundle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The bundle cannot be spawned: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidBundle {}
+
+/// A dynamic component cannot be registered because its name is already used by a different component.
+#[derive(Debug)]
+pub struct ComponentNameConflict(String);
+
+impl ComponentNameConflict {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    /// The name of the conflicting component.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for ComponentNameConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The component name \"{}\" is already registered to another type",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ComponentNameConflict {}
+
+/// A schedule cannot be run because the ordering constraints between some of its sets form a cycle.
+#[derive(Debug)]
+pub struct ScheduleCycle(Vec<String>);
+
+impl ScheduleCycle {
+    pub fn new(sets: Vec<String>) -> Self {
+        Self(sets)
+    }
+
+    /// The names of the sets whose ordering constraints form the cycle.
+    pub fn sets(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for ScheduleCycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The ordering constraints between the sets [{}] form a cycle",
+            self.0.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ScheduleCycle {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::World;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_error_question_mark() {
+        fn remove_twice(world: &mut World, entity: Entity) -> Result<(), Error> {
+            world.remove_component::<u8>(entity)?;
+            world.remove_component::<u8>(entity)?;
+            Ok(())
+        }
+
+        let mut world = World::new();
+        let entity = world.spawn((1u8, true));
+        let error = remove_twice(&mut world, entity).unwrap_err();
+        assert!(matches!(error, Error::EntityMissingComponent(_)));
+        assert_eq!(
+            error.to_string(),
+            format!("Entity {} does not have a [u8] component", entity)
+        );
+        // The wrapped error is displayed rather than returned as the source, so it is not printed twice.
+        assert!(error.source().is_none());
+
+        world.despawn(entity).unwrap();
+        let error: Error = world.despawn(entity).unwrap_err().into();
+        assert!(error.to_string().contains(&entity.to_string()));
+    }
+}
+
@@ -0,0 +1,268 @@
+//! Parent and child relationships between entities.
+//!
+//! A child has a `Parent` component and its parent has a `Children` component listing it.
+//! Both are only changed through `World::set_parent` and `World::remove_parent` so they always agree,
+//! and `World::despawn` removes a despawned entity from both sides.
+
+use crate::entity::*;
+use crate::error::*;
+use crate::world::*;
+
+/// The entity this entity is a child of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parent(Entity);
+
+impl Parent {
+    pub fn get(&self) -> Entity {
+        self.0
+    }
+}
+
+impl MapEntities for Parent {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        self.0.map_entities(map);
+    }
+}
+
+/// The entities that are children of this entity, in the order they were added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Children(Vec<Entity>);
+
+impl std::ops::Deref for Children {
+    type Target = [Entity];
+    fn deref(&self) -> &[Entity] {
+        &self.0
+    }
+}
+
+impl MapEntities for Children {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        for child in self.0.iter_mut() {
+            child.map_entities(map);
+        }
+    }
+}
+
+impl World {
+    /// Registers `Parent` and `Children` so they are remapped in scenes and included in snapshots.
+    /// This is done before anything else is registered, so their names are always free.
+    pub(crate) fn register_hierarchy(&mut self) {
+        self.register_map_entities::<Parent>("Parent").unwrap();
+        self.register_map_entities::<Children>("Children").unwrap();
+        #[cfg(feature = "serde")]
+        {
+            self.register_serializable::<Parent>("Parent").unwrap();
+            self.register_serializable::<Children>("Children").unwrap();
+        }
+    }
+
+    /// Makes `child` a child of `parent`, removing it from its previous parent if it had one.
+    /// An error is returned if `parent` is `child` or one of its descendants.
+    pub fn set_parent(&mut self, child: Entity, parent: Entity) -> Result<(), Error> {
+        for entity in [child, parent] {
+            if !self.contains(entity) {
+                return Err(NoSuchEntity::new(entity).into());
+            }
+        }
+        if parent == child || self.ancestors(parent).any(|ancestor| ancestor == child) {
+            return Err(HierarchyCycle::new(child, parent).into());
+        }
+        if self.get::<Parent>(child).is_ok_and(|p| p.0 == parent) {
+            return Ok(());
+        }
+
+        self.remove_parent(child)?;
+        self.add_component(child, Parent(parent))?;
+        if let Ok(children) = self.get_component_mut::<Children>(parent) {
+            children.0.push(child);
+        } else {
+            self.add_component(parent, Children(vec![child]))?;
+        }
+        Ok(())
+    }
+
+    /// Removes `child` from its parent, returning the parent if it had one.
+    pub fn remove_parent(&mut self, child: Entity) -> Result<Option<Entity>, NoSuchEntity> {
+        if !self.contains(child) {
+            return Err(NoSuchEntity::new(child));
+        }
+        let parent = self.remove_component::<Parent>(child).ok().map(|p| p.0);
+        if let Some(parent) = parent {
+            self.remove_child(parent, child);
+        }
+        Ok(parent)
+    }
+
+    /// Despawns an entity and all of its descendants.
+    pub fn despawn_recursive(&mut self, entity: Entity) -> Result<(), NoSuchEntity> {
+        self.remove_parent(entity)?;
+        let descendants: Vec<Entity> = self.descendants(entity).collect();
+        // Every entity in the hierarchy is despawned with them so only relations need detaching.
+        let mut pending = Vec::new();
+        for entity in std::iter::once(entity).chain(descendants) {
+            self.detach_relations(entity, &mut pending);
+            self.despawn_detached(entity)?;
+        }
+        self.despawn_cascade(pending);
+        Ok(())
+    }
+
+    /// Iterates over an entity's parent, its parent's parent and so on.
+    /// Stops early at an entity whose `Parent` is currently mutably borrowed.
+    pub fn ancestors(&self, entity: Entity) -> Ancestors<'_> {
+        Ancestors {
+            world: self,
+            current: entity,
+        }
+    }
+
+    /// Iterates depth first over an entity's children, their children and so on.
+    /// The children of an entity whose `Children` is currently mutably borrowed are skipped.
+    pub fn descendants(&self, entity: Entity) -> Descendants<'_> {
+        let mut descendants = Descendants {
+            world: self,
+            stack: Vec::new(),
+        };
+        descendants.push_children(entity);
+        descendants
+    }
+
+    /// Removes a despawning entity from its parent and leaves its children without a parent.
+    pub(crate) fn detach_hierarchy(&mut self, entity: Entity) {
+        if let Ok(parent) = self.remove_component::<Parent>(entity) {
+            self.remove_child(parent.0, entity);
+        }
+        if let Ok(children) = self.remove_component::<Children>(entity) {
+            for child in children.0 {
+                let _ = self.remove_component::<Parent>(child);
+            }
+        }
+    }
+
+    fn remove_child(&mut self, parent: Entity, child: Entity) {
+        if let Ok(children) = self.get_component_mut::<Children>(parent) {
+            children.0.retain(|c| *c != child);
+            if children.0.is_empty() {
+                let _ = self.remove_component::<Children>(parent);
+            }
+        }
+    }
+}
+
+/// Iterates over an entity's ancestors, created by `World::ancestors`.
+pub struct Ancestors<'world_borrow> {
+    world: &'world_borrow World,
+    current: Entity,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = Entity;
+    fn next(&mut self) -> Option<Entity> {
+        let parent = self.world.get::<Parent>(self.current).ok()?.0;
+        self.current = parent;
+        Some(parent)
+    }
+}
+
+/// Iterates over an entity's descendants, created by `World::descendants`.
+pub struct Descendants<'world_borrow> {
+    world: &'world_borrow World,
+    stack: Vec<Entity>,
+}
+
+impl Descendants<'_> {
+    fn push_children(&mut self, entity: Entity) {
+        if let Ok(children) = self.world.get::<Children>(entity) {
+            // Reversed so the first child is visited first.
+            self.stack.extend(children.0.iter().rev());
+        }
+    }
+}
+
+impl Iterator for Descendants<'_> {
+    type Item = Entity;
+    fn next(&mut self) -> Option<Entity> {
+        let entity = self.stack.pop()?;
+        self.push_children(entity);
+        Some(entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn children(world: &World, entity: Entity) -> Vec<Entity> {
+        world
+            .get::<Children>(entity)
+            .map(|c| c.to_vec())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_hierarchy_set_and_remove_parent() {
+        let mut world = World::new();
+        let a = world.spawn((1u8,));
+        let b = world.spawn((2u8,));
+        let c = world.spawn((3u8,));
+
+        world.set_parent(b, a).unwrap();
+        world.set_parent(c, a).unwrap();
+        assert_eq!(children(&world, a), vec![b, c]);
+        assert_eq!(world.get::<Parent>(b).unwrap().get(), a);
+
+        // Moving a child removes it from its old parent.
+        world.set_parent(c, b).unwrap();
+        assert_eq!(children(&world, a), vec![b]);
+        assert_eq!(children(&world, b), vec![c]);
+        assert_eq!(world.ancestors(c).collect::<Vec<_>>(), vec![b, a]);
+        assert_eq!(world.descendants(a).collect::<Vec<_>>(), vec![b, c]);
+
+        assert!(matches!(
+            world.set_parent(a, c),
+            Err(Error::HierarchyCycle(_))
+        ));
+        assert!(matches!(
+            world.set_parent(a, a),
+            Err(Error::HierarchyCycle(_))
+        ));
+
+        assert_eq!(world.remove_parent(c).unwrap(), Some(b));
+        assert_eq!(world.remove_parent(c).unwrap(), None);
+        assert!(world.get::<Children>(b).is_err());
+        assert_eq!(*world.get::<u8>(b).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_hierarchy_despawn() {
+        let mut world = World::new();
+        let root = world.spawn((0u8,));
+        let a = world.spawn((1u8,));
+        let a_child = world.spawn((2u8,));
+        let b = world.spawn((3u8,));
+        let b_child = world.spawn((4u8,));
+        world.set_parent(a, root).unwrap();
+        world.set_parent(a_child, a).unwrap();
+        world.set_parent(b, root).unwrap();
+        world.set_parent(b_child, b).unwrap();
+        assert_eq!(
+            world.descendants(root).collect::<Vec<_>>(),
+            vec![a, a_child, b, b_child]
+        );
+
+        // Despawning keeps the hierarchy consistent on both sides.
+        world.despawn(a).unwrap();
+        assert_eq!(children(&world, root), vec![b]);
+        assert!(world.get::<Parent>(a_child).is_err());
+        assert_eq!(world.ancestors(a_child).count(), 0);
+
+        world.despawn_recursive(b).unwrap();
+        assert!(!world.contains(b));
+        assert!(!world.contains(b_child));
+        assert!(world.get::<Children>(root).is_err());
+        assert!(world.contains(root));
+        assert!(world.contains(a_child));
+    }
+}
@@ -0,0 +1,479 @@
+//! Secondary indices from component values to the entities that have them.
+//!
+//! An index is created per component type with `World::create_index` and queried with `World::lookup`.
+//! Spawning, adding, removing and despawning update an index as they happen.
+//! Mutable access cannot see what was written, so it records which entities, or for a query which archetypes,
+//! may have changed and the next lookup re-keys only those.
+//! Other kinds of index, such as the spatial index, are kept up to date the same way through `AnyIndex`.
+
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+use crate::registry::*;
+use crate::world::*;
+
+/// A component type that can be indexed by value.
+pub trait IndexKey: Hash + Eq + Clone + Component {}
+impl<T: Hash + Eq + Clone + Component> IndexKey for T {}
+
+/// The type erased operations the world uses to keep an index up to date.
+pub(crate) trait AnyIndex: Send + Sync {
+    fn to_any(&self) -> &dyn Any;
+    /// Records the value in `column` at `row` as belonging to `entity`, replacing its previous value.
+    fn insert(&mut self, entity: Entity, column: &mut dyn ComponentVec, row: usize);
+    fn remove(&mut self, entity: Entity);
+    /// Records that the component of the entity at `index` may have been written to.
+    fn mark_changed(&self, index: EntityId);
+    /// Records that the components of every entity in an archetype may have been written to.
+    fn mark_archetype_changed(&self, archetype: usize);
+    /// Makes the next lookup rebuild the index.
+    fn mark_stale(&self);
+}
+
+/// The entities and archetypes whose indexed component may have been written to since an index was last updated.
+#[derive(Default)]
+pub(crate) struct Changes {
+    entities: HashSet<EntityId>,
+    archetypes: HashSet<usize>,
+}
+
+impl Changes {
+    pub(crate) fn entity(&mut self, index: EntityId) {
+        self.entities.insert(index);
+    }
+
+    pub(crate) fn archetype(&mut self, archetype: usize) {
+        self.archetypes.insert(archetype);
+    }
+
+    /// Forgets a despawned or removed component, which the index has already dropped.
+    pub(crate) fn forget(&mut self, entity: Entity) {
+        self.entities.remove(&entity.index);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entities.clear();
+        self.archetypes.clear();
+    }
+
+    /// Calls `f` with every changed entity that still has a `T` and its component, then forgets the changes.
+    /// If a column is mutably borrowed the changes are kept so the next update can finish them.
+    pub(crate) fn visit<T: 'static>(
+        &mut self,
+        world: &World,
+        mut f: impl FnMut(Entity, &T),
+    ) -> Result<(), ComponentAlreadyBorrowed> {
+        let id = ComponentId::of::<T>();
+        for archetype_index in self.archetypes.iter() {
+            let archetype = &world.archetypes[*archetype_index];
+            if let Some(c) = archetype.components.iter().find(|c| c.id == id) {
+                let column = read_column::<T>(&*c.data)?;
+                for (index, value) in archetype.entities.iter().zip(column.iter()) {
+                    let entity = Entity {
+                        index: *index,
+                        generation: world.entities[*index as usize].generation,
+                    };
+                    f(entity, value);
+                }
+            }
+        }
+        for index in self.entities.iter() {
+            let entity_info = world.entities[*index as usize];
+            if !entity_info.alive {
+                continue;
+            }
+            let archetype = &world.archetypes[entity_info.location.archetype_index as usize];
+            if let Some(c) = archetype.components.iter().find(|c| c.id == id) {
+                let column = read_column::<T>(&*c.data)?;
+                let entity = Entity {
+                    index: *index,
+                    generation: entity_info.generation,
+                };
+                f(
+                    entity,
+                    &column[entity_info.location.index_in_archetype as usize],
+                );
+            }
+        }
+        self.clear();
+        Ok(())
+    }
+}
+
+pub(crate) struct Index<T> {
+    entries: Mutex<IndexEntries<T>>,
+    changes: Mutex<Changes>,
+    stale: AtomicBool,
+}
+
+struct IndexEntries<T> {
+    by_value: HashMap<T, Vec<Entity>>,
+    by_entity: HashMap<Entity, T>,
+}
+
+impl<T: IndexKey> IndexEntries<T> {
+    fn insert(&mut self, entity: Entity, value: T) {
+        self.remove(entity);
+        self.by_value.entry(value.clone()).or_default().push(entity);
+        self.by_entity.insert(entity, value);
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(value) = self.by_entity.remove(&entity) {
+            let entities = self.by_value.get_mut(&value).unwrap();
+            entities.retain(|e| *e != entity);
+            if entities.is_empty() {
+                self.by_value.remove(&value);
+            }
+        }
+    }
+}
+
+impl<T: IndexKey> Index<T> {
+    fn new() -> Self {
+        Self {
+            entries: Mutex::new(IndexEntries {
+                by_value: HashMap::new(),
+                by_entity: HashMap::new(),
+            }),
+            changes: Mutex::new(Changes::default()),
+            // Built by the first lookup.
+            stale: AtomicBool::new(true),
+        }
+    }
+
+    fn lookup(&self, world: &World, value: &T) -> Result<Vec<Entity>, Error> {
+        let mut entries = self.entries.lock().unwrap();
+        // Cleared before building so a write that starts during the build marks it stale again.
+        if self.stale.swap(false, Ordering::AcqRel) {
+            match Self::build(world) {
+                Ok(built) => {
+                    *entries = built;
+                    self.changes.lock().unwrap().clear();
+                }
+                Err(e) => {
+                    self.stale.store(true, Ordering::Release);
+                    return Err(e.into());
+                }
+            }
+        } else {
+            self.changes
+                .lock()
+                .unwrap()
+                .visit::<T>(world, |entity, value| entries.insert(entity, value.clone()))?;
+        }
+        Ok(entries.by_value.get(value).cloned().unwrap_or_default())
+    }
+
+    fn build(world: &World) -> Result<IndexEntries<T>, ComponentAlreadyBorrowed> {
+        let mut entries = IndexEntries {
+            by_value: HashMap::new(),
+            by_entity: HashMap::new(),
+        };
+        visit_components::<T>(world, |entity, value| entries.insert(entity, value.clone()))?;
+        Ok(entries)
+    }
+}
+
+/// Calls `f` with every live entity that has a `T` and its component, for rebuilding an index.
+pub(crate) fn visit_components<T: 'static>(
+    world: &World,
+    mut f: impl FnMut(Entity, &T),
+) -> Result<(), ComponentAlreadyBorrowed> {
+    let id = ComponentId::of::<T>();
+    for archetype in world.archetypes.iter() {
+        if let Some(c) = archetype.components.iter().find(|c| c.id == id) {
+            let column = read_column::<T>(&*c.data)?;
+            for (index, value) in archetype.entities.iter().zip(column.iter()) {
+                let entity = Entity {
+                    index: *index,
+                    generation: world.entities[*index as usize].generation,
+                };
+                f(entity, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<T: IndexKey> AnyIndex for Index<T> {
+    fn to_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn insert(&mut self, entity: Entity, column: &mut dyn ComponentVec, row: usize) {
+        // A stale index is rebuilt from scratch so there is no point updating it.
+        if !*self.stale.get_mut() {
+            let value = component_vec_to_mut::<T>(column)[row].clone();
+            self.entries.get_mut().unwrap().insert(entity, value);
+        }
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if !*self.stale.get_mut() {
+            self.entries.get_mut().unwrap().remove(entity);
+            self.changes.get_mut().unwrap().forget(entity);
+        }
+    }
+
+    fn mark_changed(&self, index: EntityId) {
+        self.changes.lock().unwrap().entity(index);
+    }
+
+    fn mark_archetype_changed(&self, archetype: usize) {
+        self.changes.lock().unwrap().archetype(archetype);
+    }
+
+    fn mark_stale(&self) {
+        self.stale.store(true, Ordering::Release);
+    }
+}
+
+impl World {
+    /// Starts indexing `T` by value so entities can be found with `lookup`.
+    /// Creating an index that already exists does nothing.
+    pub fn create_index<T: IndexKey>(&mut self) {
+        if self
+            .find_index::<Index<T>>(ComponentId::of::<T>())
+            .is_none()
+        {
+            self.add_index(ComponentId::of::<T>(), Box::new(Index::<T>::new()));
+        }
+    }
+
+    /// Stops indexing `T` by value.
+    pub fn drop_index<T: IndexKey>(&mut self) {
+        self.remove_index::<Index<T>>(ComponentId::of::<T>());
+    }
+
+    /// Finds every entity whose `T` component equals `value`, in no particular order.
+    /// An error is returned if `T` is not indexed, or if the index must be rebuilt
+    /// while `T` is mutably borrowed.
+    pub fn lookup<T: IndexKey>(&self, value: &T) -> Result<Vec<Entity>, Error> {
+        self.find_index::<Index<T>>(ComponentId::of::<T>())
+            .ok_or_else(|| NoSuchIndex::new(std::any::type_name::<T>()))?
+            .lookup(self, value)
+    }
+
+    /// Starts keeping `index` up to date with the components with the given id.
+    pub(crate) fn add_index(&mut self, id: ComponentId, index: Box<dyn AnyIndex>) {
+        self.indices.entry(id).or_default().push(index);
+    }
+
+    pub(crate) fn remove_index<I: AnyIndex + 'static>(&mut self, id: ComponentId) {
+        if let Some(indices) = self.indices.get_mut(&id) {
+            indices.retain(|index| !index.to_any().is::<I>());
+            if indices.is_empty() {
+                self.indices.remove(&id);
+            }
+        }
+    }
+
+    /// Finds the index of type `I` over the components with the given id.
+    pub(crate) fn find_index<I: AnyIndex + 'static>(&self, id: ComponentId) -> Option<&I> {
+        self.indices
+            .get(&id)?
+            .iter()
+            .find_map(|index| index.to_any().downcast_ref::<I>())
+    }
+
+    /// Records the indexed components of the live entity at `index` after it has been placed.
+    pub(crate) fn index_entity(&mut self, index: EntityId) {
+        if self.indices.is_empty() {
+            return;
+        }
+        let entity_info = self.entities[index as usize];
+        let entity = Entity {
+            index,
+            generation: entity_info.generation,
+        };
+        let archetype = &mut self.archetypes[entity_info.location.archetype_index as usize];
+        for c in archetype.components.iter_mut() {
+            for component_index in self.indices.get_mut(&c.id).into_iter().flatten() {
+                component_index.insert(
+                    entity,
+                    &mut *c.data,
+                    entity_info.location.index_in_archetype as usize,
+                );
+            }
+        }
+    }
+
+    /// Forgets the indexed components of the live entity at `index` before it is removed.
+    pub(crate) fn unindex_entity(&mut self, index: EntityId) {
+        if self.indices.is_empty() {
+            return;
+        }
+        let entity_info = self.entities[index as usize];
+        let entity = Entity {
+            index,
+            generation: entity_info.generation,
+        };
+        let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+        for c in archetype.components.iter() {
+            for component_index in self.indices.get_mut(&c.id).into_iter().flatten() {
+                component_index.remove(entity);
+            }
+        }
+    }
+
+    /// Forgets a single indexed component of an entity before it is removed.
+    pub(crate) fn unindex_component(&mut self, entity: Entity, id: ComponentId) {
+        for component_index in self.indices.get_mut(&id).into_iter().flatten() {
+            component_index.remove(entity);
+        }
+    }
+
+    /// Records that the `id` component of the entity at `index` may be written to.
+    pub(crate) fn mark_component_changed(&self, id: ComponentId, index: EntityId) {
+        for component_index in self.indices.get(&id).into_iter().flatten() {
+            component_index.mark_changed(index);
+        }
+    }
+
+    /// Records that the `id` components of every entity in an archetype may be written to.
+    pub(crate) fn mark_column_changed(&self, id: ComponentId, archetype: usize) {
+        for component_index in self.indices.get(&id).into_iter().flatten() {
+            component_index.mark_archetype_changed(archetype);
+        }
+    }
+
+    /// Marks every index stale after the world's contents were replaced wholesale.
+    pub(crate) fn mark_indices_stale(&self) {
+        for component_index in self.indices.values().flatten() {
+            component_index.mark_stale();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    struct NetId(u32);
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    enum Team {
+        Red,
+        Blue,
+    }
+
+    fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+        entities.sort();
+        entities
+    }
+
+    #[test]
+    fn test_index_structural_changes() {
+        let mut world = World::new();
+        assert!(matches!(
+            world.lookup(&NetId(1)),
+            Err(Error::NoSuchIndex(_))
+        ));
+
+        let a = world.spawn((NetId(1), Team::Red));
+        world.create_index::<NetId>();
+        world.create_index::<Team>();
+        let b = world.spawn((NetId(2), Team::Red));
+        let c = world.spawn((Team::Blue,));
+        assert_eq!(world.lookup(&NetId(1)).unwrap(), vec![a]);
+        assert_eq!(sorted(world.lookup(&Team::Red).unwrap()), vec![a, b]);
+
+        world.add_component(c, NetId(3)).unwrap();
+        world.add_component(a, NetId(4)).unwrap();
+        assert_eq!(world.lookup(&NetId(3)).unwrap(), vec![c]);
+        assert!(world.lookup(&NetId(1)).unwrap().is_empty());
+        assert_eq!(world.lookup(&NetId(4)).unwrap(), vec![a]);
+
+        world.remove_component::<Team>(a).unwrap();
+        assert_eq!(world.lookup(&Team::Red).unwrap(), vec![b]);
+
+        world.despawn(b).unwrap();
+        assert!(world.lookup(&Team::Red).unwrap().is_empty());
+        assert!(world.lookup(&NetId(2)).unwrap().is_empty());
+        // The despawned slot is reused with a new generation.
+        let d = world.spawn((NetId(2),));
+        assert_eq!(world.lookup(&NetId(2)).unwrap(), vec![d]);
+    }
+
+    #[test]
+    fn test_index_mutable_access() {
+        let mut world = World::new();
+        world.create_index::<NetId>();
+        let a = world.spawn((NetId(1),));
+        let b = world.spawn((NetId(2),));
+        assert_eq!(world.lookup(&NetId(1)).unwrap(), vec![a]);
+
+        world.get_component_mut::<NetId>(a).unwrap().0 = 10;
+        assert!(world.lookup(&NetId(1)).unwrap().is_empty());
+        assert_eq!(world.lookup(&NetId(10)).unwrap(), vec![a]);
+
+        {
+            let mut query = world.query::<(&mut NetId,)>().unwrap();
+            // The index cannot be rebuilt while the query holds the column.
+            assert!(matches!(
+                world.lookup(&NetId(2)),
+                Err(Error::ComponentAlreadyBorrowed(_))
+            ));
+            for id in query.iter() {
+                id.0 += 100;
+            }
+        }
+        assert_eq!(world.lookup(&NetId(110)).unwrap(), vec![a]);
+        assert_eq!(world.lookup(&NetId(102)).unwrap(), vec![b]);
+    }
+
+    #[test]
+    fn test_index_rekeys_only_changes() {
+        let mut world = World::new();
+        world.create_index::<NetId>();
+        let entities: Vec<Entity> = (0..100).map(|i| world.spawn((NetId(i),))).collect();
+        let other = world.spawn((Team::Red,));
+        assert_eq!(world.lookup(&NetId(5)).unwrap(), vec![entities[5]]);
+
+        let changed = |world: &World| {
+            let index = world
+                .find_index::<Index<NetId>>(ComponentId::of::<NetId>())
+                .unwrap();
+            assert!(!index.stale.load(Ordering::Acquire));
+            index.changes.lock().unwrap().entities.len()
+        };
+        world.get_component_mut::<NetId>(entities[5]).unwrap().0 = 500;
+        assert!(world.get_component_mut::<NetId>(other).is_err());
+        assert_eq!(changed(&world), 1);
+        assert_eq!(world.lookup(&NetId(500)).unwrap(), vec![entities[5]]);
+        assert!(world.lookup(&NetId(5)).unwrap().is_empty());
+        assert_eq!(changed(&world), 0);
+
+        // A changed entity that is despawned before the next lookup is dropped from the index.
+        world.get_component_mut::<NetId>(entities[6]).unwrap().0 = 600;
+        world.despawn(entities[6]).unwrap();
+        assert!(world.lookup(&NetId(600)).unwrap().is_empty());
+        assert!(world.lookup(&NetId(6)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_index_changes_survive_archetype_moves() {
+        struct Other;
+
+        let mut world = World::new();
+        world.create_index::<NetId>();
+        let e = world.spawn((NetId(1), Other));
+        assert_eq!(world.lookup(&NetId(1)).unwrap(), vec![e]);
+
+        // The query marks the archetype the entity then leaves.
+        for id in world.query::<(&mut NetId,)>().unwrap().iter() {
+            id.0 = 2;
+        }
+        world.remove_component::<Other>(e).unwrap();
+        assert_eq!(world.lookup(&NetId(2)).unwrap(), vec![e]);
+        assert!(world.lookup(&NetId(1)).unwrap().is_empty());
+    }
+}
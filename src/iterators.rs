@@ -11,7 +11,7 @@ macro_rules! impl_zip {
         }
 
         impl<A: Iterator, $($T: Iterator,)*> $name<A, $($T,)*> {
-            #[allow(non_snake_case)]
+            #[allow(non_snake_case, clippy::too_many_arguments)]
             pub fn new (A: A, $($T: $T,)*) -> Self {
                 Self {
                     inner: A$(.zip($T))*
@@ -68,22 +68,16 @@ impl<I: Iterator> Iterator for ChainedIterator<I> {
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
         // Chain the iterators together.
-        // If the end of one iterator is reached go to the next.
+        // If the end of one iterator is reached go to the next,
+        // skipping any that are empty.
 
-        match self.current_iter {
-            Some(ref mut iter) => match iter.next() {
-                None => {
-                    self.current_iter = self.iterators.pop();
-                    if let Some(ref mut iter) = self.current_iter {
-                        iter.next()
-                    } else {
-                        None
-                    }
-                }
-                item => item,
-            },
-            None => None,
+        while let Some(ref mut iter) = self.current_iter {
+            match iter.next() {
+                None => self.current_iter = self.iterators.pop(),
+                item => return item,
+            }
         }
+        None
     }
 
     #[inline]
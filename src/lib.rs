@@ -0,0 +1,32 @@
+pub mod entity;
+pub mod component;
+pub mod archetype;
+pub mod world;
+pub mod query;
+pub mod iterators;
+pub mod error;
+pub mod registry;
+pub mod dynamic;
+pub mod hierarchy;
+pub mod relation;
+pub mod index;
+pub mod spatial;
+pub mod required;
+pub mod prefab;
+pub mod clone;
+pub mod transfer;
+pub mod schedule;
+pub mod time;
+pub mod rollback;
+#[cfg(feature = "serde")]
+pub mod delta;
+#[cfg(feature = "serde")]
+pub mod scene;
+#[cfg(feature = "serde")]
+pub mod snapshot;
+
+pub use error::Error;
+pub use kecs_derive::Bundle;
+
+// Lets the derive macros refer to this crate as `::kecs` from inside it too.
+extern crate self as kecs;
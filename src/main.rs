@@ -1,11 +0,0 @@
-pub mod entity;
-pub mod component;
-pub mod archetype;
-pub mod world;
-pub mod query;
-pub mod iterators;
-pub mod error;
-
-fn main() {
-    println!("Hello, world!");
-}
@@ -0,0 +1,401 @@
+//! Prefabs: reusable sets of component values that can be spawned many times.
+//!
+//! A `Prefab` holds one value of each of its component types along with a way to clone it,
+//! so it can be built in code, copied from an existing entity or read from a scene.
+//! `World::instantiate_batch` finds the prefab's archetype once and clones each component
+//! straight into its column, which is much cheaper than building and spawning a bundle per entity.
+
+use crate::archetype::*;
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+use crate::registry::*;
+use crate::world::*;
+
+/// A set of cloneable component values that can be spawned as new entities.
+#[derive(Default)]
+pub struct Prefab {
+    /// Sorted by id.
+    components: Vec<PrefabComponent>,
+}
+
+struct PrefabComponent {
+    /// A single row holding the component.
+    value: ComponentStore,
+    clone_into: CloneIntoFn,
+    /// How a world instantiating the prefab learns the component's type and finds its id.
+    source: ComponentSource,
+}
+
+/// Where a prefab component came from.
+#[derive(Clone)]
+enum ComponentSource {
+    /// Added by its Rust type, which registers itself.
+    Type(fn(&mut ComponentRegistry) -> ComponentId),
+    /// Copied from a world, whose registry info is imported by the world it is instantiated in.
+    /// Dynamic components are matched up by name since their ids differ between worlds.
+    World(ComponentInfo),
+}
+
+impl Clone for PrefabComponent {
+    fn clone(&self) -> Self {
+        let mut value = self.value.new_same_type();
+        (self.clone_into)(&*self.value.data, &mut *value.data, 1);
+        Self {
+            value,
+            clone_into: self.clone_into,
+            source: self.source.clone(),
+        }
+    }
+}
+
+impl Clone for Prefab {
+    fn clone(&self) -> Self {
+        Self {
+            components: self.components.clone(),
+        }
+    }
+}
+
+impl Prefab {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a component, replacing any existing component of the same type.
+    pub fn with<T: Clone + Component>(mut self, t: T) -> Self {
+        self.insert(t);
+        self
+    }
+
+    /// Adds a component, replacing any existing component of the same type.
+    pub fn insert<T: Clone + Component>(&mut self, t: T) {
+        self.insert_component(PrefabComponent {
+            value: ComponentStore::from_value(t),
+            clone_into: clone_into_column::<T>,
+            source: ComponentSource::Type(ComponentRegistry::register_type::<T>),
+        });
+    }
+
+    /// Removes the component of type `T`, returning true if there was one.
+    pub fn remove<T: 'static>(&mut self) -> bool {
+        let id = ComponentId::of::<T>();
+        let len = self.components.len();
+        self.components.retain(|c| c.value.id != id);
+        self.components.len() != len
+    }
+
+    /// The components an instance of the prefab is given.
+    pub fn component_ids(&self) -> Vec<ComponentId> {
+        self.components.iter().map(|c| c.value.id).collect()
+    }
+
+    fn insert_component(&mut self, component: PrefabComponent) {
+        match self
+            .components
+            .binary_search_by_key(&component.value.id, |c| c.value.id)
+        {
+            Ok(i) => self.components[i] = component,
+            Err(i) => self.components.insert(i, component),
+        }
+    }
+}
+
+impl World {
+    /// Creates a prefab with a copy of every component of an entity.
+    /// Every component type other than a dynamic one must have been registered with `register_clone`.
+    pub fn prefab_from_entity(&self, entity: Entity) -> Result<Prefab, Error> {
+        let entity_info = self
+            .entity_info(entity)
+            .ok_or_else(|| NoSuchEntity::new(entity))?;
+        let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+        let row = entity_info.location.index_in_archetype as usize;
+
+        let mut prefab = Prefab::new();
+        for c in archetype.components.iter() {
+            let (value, clone_into, info) = self
+                .registry
+                .info(c.id)
+                .and_then(|info| {
+                    Some((info.clone_component(&*c.data, row)?, info.clone_into?, info))
+                })
+                .ok_or_else(|| ComponentNotRegistered::new(self.component_name(c.id)))?;
+            prefab.insert_component(PrefabComponent {
+                value: value?,
+                clone_into,
+                source: ComponentSource::World(info.clone()),
+            });
+        }
+        Ok(prefab)
+    }
+
+    /// Creates a prefab from the components of an entity in a scene.
+    /// Every component type must have been registered with both `register_serializable` and `register_clone`.
+    #[cfg(feature = "serde")]
+    pub fn prefab_from_scene_entity(
+        &self,
+        scene_entity: &crate::scene::SceneEntity,
+    ) -> Result<Prefab, Error> {
+        let mut prefab = Prefab::new();
+        for value in self.deserialize_components(&scene_entity.components)? {
+            let (clone_into, info) = self
+                .registry
+                .info(value.id)
+                .and_then(|info| Some((info.clone_into?, info)))
+                .ok_or_else(|| ComponentNotRegistered::new(self.component_name(value.id)))?;
+            prefab.insert_component(PrefabComponent {
+                value,
+                clone_into,
+                source: ComponentSource::World(info.clone()),
+            });
+        }
+        Ok(prefab)
+    }
+
+    /// Spawns an entity with a clone of each of the prefab's components.
+    ///
+    /// # Panics
+    /// Panics if the prefab was copied from another world and has a dynamic component
+    /// whose name this world uses for a different component.
+    pub fn instantiate(&mut self, prefab: &Prefab) -> Entity {
+        self.spawn_prefab(prefab, 1, || ())[0]
+    }
+
+    /// Spawns an entity from a prefab with the components of `overrides` in place of the prefab's
+    /// components of the same types.
+    pub fn instantiate_with(&mut self, prefab: &Prefab, overrides: impl ComponentBundle) -> Entity {
+        let mut overrides = Some(overrides);
+        self.spawn_prefab(prefab, 1, || overrides.take().unwrap())[0]
+    }
+
+    /// Spawns `count` entities from a prefab into the same archetype.
+    pub fn instantiate_batch(&mut self, prefab: &Prefab, count: usize) -> Vec<Entity> {
+        self.spawn_prefab(prefab, count, || ())
+    }
+
+    /// Spawns `count` entities, each with the prefab's components, the components returned by
+    /// `overrides` and any components those require.
+    fn spawn_prefab<B: ComponentBundle>(
+        &mut self,
+        prefab: &Prefab,
+        count: usize,
+        mut overrides: impl FnMut() -> B,
+    ) -> Vec<Entity> {
+        let mut override_ids = Vec::new();
+        B::component_ids(&mut override_ids);
+        // Each component's id in this world, which differs from the prefab's for a dynamic component from another world.
+        let mut kept: Vec<(ComponentId, &PrefabComponent)> = Vec::new();
+        for c in prefab.components.iter() {
+            let id = match &c.source {
+                ComponentSource::Type(register) => register(&mut self.registry),
+                ComponentSource::World(info) => self
+                    .registry
+                    .import_info(info)
+                    .unwrap_or_else(|e| panic!("{}", e)),
+            };
+            if !override_ids.contains(&id) {
+                kept.push((id, c));
+            }
+        }
+
+        let mut ids: Vec<ComponentId> = kept.iter().map(|(id, _)| *id).collect();
+        ids.extend(override_ids.iter().copied());
+        // The first entity's required components also give the archetype's columns,
+        // so they are built even when no entities are spawned.
+        let mut required = vec![self.required_stores(&ids)];
+        required.extend((1..count).map(|_| self.required_stores(&ids)));
+        let mut archetype_ids = ids.clone();
+        archetype_ids.extend(required[0].iter().map(|c| c.id));
+        archetype_ids.sort_unstable();
+        let bundle_id = calculate_bundle_id(&archetype_ids);
+
+        let archetype_index = if let Some(archetype) = self.bundle_id_to_archetype.get(&bundle_id) {
+            *archetype
+        } else {
+            assert!(
+                archetype_ids.windows(2).all(|x| x[0] != x[1]),
+                "`ComponentBundle`s cannot have duplicate types"
+            );
+            B::register_types(&mut self.registry);
+            let mut archetype = Archetype::new();
+            B::add_columns(&mut archetype);
+            for (id, c) in kept.iter() {
+                let mut column = c.value.new_same_type();
+                column.id = *id;
+                archetype.components.push(column);
+            }
+            for c in required[0].iter() {
+                archetype.components.push(c.new_same_type());
+            }
+            archetype.components.sort_unstable_by_key(|c| c.id);
+            let index = self.archetypes.len();
+
+            self.bundle_id_to_archetype.insert(bundle_id, index);
+            self.archetypes.push(archetype);
+            index
+        };
+
+        let entities: Vec<Entity> = (0..count).map(|_| self.reserve_entity()).collect();
+        let archetype = &mut self.archetypes[archetype_index];
+        let first_row = archetype.len();
+        let column = |archetype: &Archetype, id: ComponentId| {
+            archetype
+                .components
+                .binary_search_by_key(&id, |c| c.id)
+                .unwrap()
+        };
+        // Every instance has the same value so each column is filled in one go.
+        for (id, c) in kept.iter() {
+            let i = column(archetype, *id);
+            (c.clone_into)(&*c.value.data, &mut *archetype.components[i].data, count);
+        }
+        for (entity, stores) in entities.iter().zip(required) {
+            archetype.entities.push(entity.index);
+            overrides().push_components(archetype);
+            for mut store in stores {
+                let i = column(archetype, store.id);
+                store.data.migrate(0, &mut *archetype.components[i].data);
+            }
+        }
+
+        for (row, entity) in entities.iter().enumerate() {
+            self.entities[entity.index as usize] = EntityInfo {
+                location: EntityLocation {
+                    archetype_index: archetype_index as EntityId,
+                    index_in_archetype: (first_row + row) as EntityId,
+                },
+                generation: entity.generation,
+                alive: true,
+            };
+            self.index_entity(entity.index);
+        }
+        entities
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Health(u32);
+    #[derive(Debug, Clone, PartialEq)]
+    struct Name(String);
+
+    #[test]
+    fn test_prefab_instantiate() {
+        let mut world = World::new();
+        let enemy = Prefab::new()
+            .with(Health(100))
+            .with(Name("goblin".to_string()))
+            .with(Health(50));
+        assert_eq!(enemy.component_ids().len(), 2);
+
+        let a = world.instantiate(&enemy);
+        let batch = world.instantiate_batch(&enemy, 500);
+        let b = world.instantiate_with(&enemy, (Health(10), 1u8));
+        assert_eq!(batch.len(), 500);
+        assert_eq!(*world.get::<Health>(a).unwrap(), Health(50));
+        for entity in batch.iter() {
+            assert_eq!(*world.get::<Health>(*entity).unwrap(), Health(50));
+            assert_eq!(world.get::<Name>(*entity).unwrap().0, "goblin");
+        }
+        assert_eq!(*world.get::<Health>(b).unwrap(), Health(10));
+        assert_eq!(world.get::<Name>(b).unwrap().0, "goblin");
+        assert_eq!(world.component_ids(b).unwrap().len(), 3);
+
+        // Instances are independent of each other and of the prefab.
+        world.get_component_mut::<Health>(batch[3]).unwrap().0 = 1;
+        assert_eq!(*world.get::<Health>(batch[4]).unwrap(), Health(50));
+        world.despawn(batch[0]).unwrap();
+        assert_eq!(*world.get::<Health>(batch[499]).unwrap(), Health(50));
+        let c = world.instantiate(&enemy);
+        assert_eq!(*world.get::<Health>(c).unwrap(), Health(50));
+    }
+
+    #[test]
+    fn test_prefab_from_entity() {
+        let mut world = World::new();
+        world.register_clone::<Health>("Health").unwrap();
+        let original = world.spawn((Health(7), Name("orc".to_string())));
+        assert!(matches!(
+            world.prefab_from_entity(original),
+            Err(Error::ComponentNotRegistered(_))
+        ));
+
+        world.register_clone::<Name>("Name").unwrap();
+        let mut prefab = world.prefab_from_entity(original).unwrap();
+        prefab.remove::<Name>();
+        let copy = world.instantiate(&prefab.clone().with(3u8));
+        assert_eq!(*world.get::<Health>(copy).unwrap(), Health(7));
+        assert!(world.get::<Name>(copy).is_err());
+        assert_eq!(*world.get::<u8>(copy).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_prefab_into_another_world() {
+        let mut world = World::new();
+        world.register_clone::<Health>("Health").unwrap();
+        let bytes = world
+            .register_dynamic_component("Bytes", std::alloc::Layout::new::<[u8; 4]>())
+            .unwrap();
+        let original = world.spawn((Health(7),));
+        world
+            .insert_dynamic(original, bytes, &[1, 2, 3, 4])
+            .unwrap();
+        let prefab = world.prefab_from_entity(original).unwrap();
+
+        // The other world uses the first dynamic id for a different component.
+        let mut other = World::new();
+        let word = other
+            .register_dynamic_component("Word", std::alloc::Layout::new::<u16>())
+            .unwrap();
+        let copy = other.instantiate(&prefab);
+        let other_bytes = other.registry().id_of("Bytes").unwrap();
+        assert_ne!(other_bytes, word);
+        assert_eq!(
+            &*other.get_dynamic(copy, other_bytes).unwrap(),
+            &[1, 2, 3, 4]
+        );
+        assert!(other.get_dynamic(copy, word).is_err());
+        assert_eq!(*other.get::<Health>(copy).unwrap(), Health(7));
+        // The other world learns the types along with their operations.
+        let health = other.registry().id_of("Health").unwrap();
+        assert!(other.registry().info(health).unwrap().can_clone());
+    }
+
+    #[test]
+    #[should_panic(expected = "already registered to another type")]
+    fn test_prefab_name_conflict() {
+        let mut world = World::new();
+        let bytes = world
+            .register_dynamic_component("Bytes", std::alloc::Layout::new::<u8>())
+            .unwrap();
+        let original = world.spawn(());
+        world.insert_dynamic(original, bytes, &[1]).unwrap();
+        let prefab = world.prefab_from_entity(original).unwrap();
+
+        let mut other = World::new();
+        other
+            .register_dynamic_component("Bytes", std::alloc::Layout::new::<u16>())
+            .unwrap();
+        other.instantiate(&prefab);
+    }
+
+    #[test]
+    fn test_prefab_empty_batch() {
+        let mut world = World::new();
+        world.register_required::<Health, Name>(|| Name("unnamed".to_string()));
+        let prefab = Prefab::new().with(Health(1));
+        assert!(world.instantiate_batch(&prefab, 0).is_empty());
+
+        // The archetype created for the empty batch has the required columns.
+        let mut ids = vec![ComponentId::of::<Health>(), ComponentId::of::<Name>()];
+        ids.sort_unstable();
+        assert!(world
+            .bundle_id_to_archetype
+            .contains_key(&calculate_bundle_id(&ids)));
+        let entity = world.instantiate(&prefab);
+        assert_eq!(world.get::<Name>(entity).unwrap().0, "unnamed");
+    }
+}
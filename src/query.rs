@@ -13,13 +13,11 @@
 use crate::iterators::*;
 use crate::error::*;
 use crate::world::*;
-use crate::component::*;
 use crate::archetype::*;
-use crate::entity::*;
+use crate::component::*;
 
 use std::iter::Zip;
 use std::sync::{RwLockReadGuard, RwLockWriteGuard};
-use std::{any::TypeId, usize};
 
 pub trait SystemParameter {
     // This is used to specify how and what to request from the World.
@@ -99,6 +97,25 @@ impl<'a, 'world_borrow, T: 'a> FetchItem<'a> for Single<'world_borrow, T> {
     }
 }
 
+/// An immutable borrow of a single component on an entity, returned by `World::get`.
+pub struct ComponentRef<'world_borrow, T> {
+    borrow: RwLockReadGuard<'world_borrow, Vec<T>>,
+    row: usize,
+}
+
+impl<'world_borrow, T> ComponentRef<'world_borrow, T> {
+    pub(crate) fn new(borrow: RwLockReadGuard<'world_borrow, Vec<T>>, row: usize) -> Self {
+        Self { borrow, row }
+    }
+}
+
+impl<T> std::ops::Deref for ComponentRef<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.borrow[self.row]
+    }
+}
+
 pub struct SingleMut<'world_borrow, T> {
     borrow: RwLockWriteGuard<'world_borrow, Vec<T>>,
 }
@@ -110,43 +127,54 @@ impl<'a, 'world_borrow, T: 'a> FetchItem<'a> for SingleMut<'world_borrow, T> {
     }
 }
 
-impl<'world_borrow, T: 'static> Fetch<'world_borrow> for &T {
-    type Item = Single<'world_borrow, T>;
-    fn fetch(world: &'world_borrow World) -> Result<Self::Item, FetchError> {
-        // The archetypes must be found here.
-        let type_id = TypeId::of::<T>();
-        for archetype in world.archetypes.iter() {
-            for (i, c) in archetype.components.iter().enumerate() {
-                if c.type_id == type_id {
-                    let borrow = archetype.get(i).try_read().unwrap();
-                    return Ok(Single { borrow });
+/// Finds the archetype and component index of the only `T` in the world.
+/// Archetypes that have a `T` column but no entities are skipped.
+pub(crate) fn find_single<T: 'static>(world: &World) -> Result<(&Archetype, usize), FetchError> {
+    let id = ComponentId::of::<T>();
+    let mut found = None;
+    for archetype in world.archetypes.iter() {
+        if let Some(i) = archetype.components.iter().position(|c| c.id == id) {
+            match archetype.entities.len() {
+                0 => {}
+                1 if found.is_none() => found = Some((archetype, i)),
+                _ => {
+                    return Err(FetchError::MultipleComponentsExist(
+                        MultipleComponentsExist::new::<T>(),
+                    ))
                 }
             }
         }
+    }
+
+    found.ok_or_else(|| FetchError::ComponentDoesNotExist(ComponentDoesNotExist::new::<T>()))
+}
 
-        Err(FetchError::ComponentDoesNotExist(
-            ComponentDoesNotExist::new::<T>(),
-        ))
+impl<'world_borrow, T: 'static> Fetch<'world_borrow> for &T {
+    type Item = Single<'world_borrow, T>;
+    fn fetch(world: &'world_borrow World) -> Result<Self::Item, FetchError> {
+        let (archetype, i) = find_single::<T>(world)?;
+        if let Ok(borrow) = archetype.get(i).try_read() {
+            Ok(Single { borrow })
+        } else {
+            Err(FetchError::ComponentAlreadyBorrowed(
+                ComponentAlreadyBorrowed::new::<T>(),
+            ))
+        }
     }
 }
 
 impl<'world_borrow, T: 'static> Fetch<'world_borrow> for &mut T {
     type Item = SingleMut<'world_borrow, T>;
     fn fetch(world: &'world_borrow World) -> Result<Self::Item, FetchError> {
-        // The archetypes must be found here.
-        let type_id = TypeId::of::<T>();
-        for archetype in world.archetypes.iter() {
-            for (i, c) in archetype.components.iter().enumerate() {
-                if c.type_id == type_id {
-                    let borrow = archetype.get(i).try_write().unwrap();
-                    return Ok(SingleMut { borrow });
-                }
-            }
+        let (archetype, i) = find_single::<T>(world)?;
+        if let Ok(borrow) = archetype.get(i).try_write() {
+            world.mark_component_changed(ComponentId::of::<T>(), archetype.entities[0]);
+            Ok(SingleMut { borrow })
+        } else {
+            Err(FetchError::ComponentAlreadyBorrowed(
+                ComponentAlreadyBorrowed::new::<T>(),
+            ))
         }
-
-        Err(FetchError::ComponentDoesNotExist(
-            ComponentDoesNotExist::new::<T>(),
-        ))
     }
 }
 
@@ -166,12 +194,12 @@ impl<'a, T: 'static> QueryParameterFetch<'a> for ReadQueryParameterFetch<T> {
     type FetchItem = RwLockReadGuard<'a, Vec<T>>;
     fn fetch(world: &'a World, archetype: usize) -> Result<Self::FetchItem, FetchError> {
         let archetype = &world.archetypes[archetype];
-        let type_id = TypeId::of::<T>();
+        let id = ComponentId::of::<T>();
 
         let index = archetype
             .components
             .iter()
-            .position(|c| c.type_id == type_id)
+            .position(|c| c.id == id)
             .unwrap();
         if let Ok(read_guard) = archetype.get(index).try_read() {
             Ok(read_guard)
@@ -196,8 +224,8 @@ impl<T: 'static> QueryParameter for &T {
     type QueryParameterFetch = ReadQueryParameterFetch<T>;
 
     fn matches_archetype(archetype: &Archetype) -> bool {
-        let type_id = TypeId::of::<T>();
-        archetype.components.iter().any(|c| c.type_id == type_id)
+        let id = ComponentId::of::<T>();
+        archetype.components.iter().any(|c| c.id == id)
     }
 }
 
@@ -205,8 +233,8 @@ impl<T: 'static> QueryParameter for &mut T {
     type QueryParameterFetch = WriteQueryParameterFetch<T>;
 
     fn matches_archetype(archetype: &Archetype) -> bool {
-        let type_id = TypeId::of::<T>();
-        archetype.components.iter().any(|c| c.type_id == type_id)
+        let id = ComponentId::of::<T>();
+        archetype.components.iter().any(|c| c.id == id)
     }
 }
 
@@ -221,15 +249,15 @@ impl<'world_borrow, T: 'static> QueryParameterFetch<'world_borrow> for Has<T> {
     type FetchItem = bool;
     fn fetch(world: &'world_borrow World, archetype: usize) -> Result<Self::FetchItem, FetchError> {
         let archetype = &world.archetypes[archetype];
-        let type_id = TypeId::of::<T>();
+        let id = ComponentId::of::<T>();
 
-        let contains = archetype.components.iter().any(|c| c.type_id == type_id);
+        let contains = archetype.components.iter().any(|c| c.id == id);
         Ok(contains)
     }
 }
 
 // If a boolean value is reported, just repeat its result.
-impl<'a, 'world_borrow> QueryIter<'a> for bool {
+impl<'a> QueryIter<'a> for bool {
     type Iter = std::iter::Repeat<bool>;
     fn iter(&'a mut self) -> Self::Iter {
         std::iter::repeat(*self)
@@ -251,16 +279,17 @@ pub struct WriteQueryParameterFetch<T> {
 
 impl<'world_borrow, T: 'static> QueryParameterFetch<'world_borrow> for WriteQueryParameterFetch<T> {
     type FetchItem = RwLockWriteGuard<'world_borrow, Vec<T>>;
-    fn fetch(world: &'world_borrow World, archetype: usize) -> Result<Self::FetchItem, FetchError> {
-        let archetype = &world.archetypes[archetype];
-        let type_id = TypeId::of::<T>();
+    fn fetch(world: &'world_borrow World, archetype_index: usize) -> Result<Self::FetchItem, FetchError> {
+        let archetype = &world.archetypes[archetype_index];
+        let id = ComponentId::of::<T>();
 
         let index = archetype
             .components
             .iter()
-            .position(|c| c.type_id == type_id)
+            .position(|c| c.id == id)
             .unwrap();
         if let Ok(write_guard) = archetype.get(index).try_write() {
+            world.mark_column_changed(id, archetype_index);
             Ok(write_guard)
         } else {
             Err(FetchError::ComponentAlreadyBorrowed(
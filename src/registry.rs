@@ -0,0 +1,620 @@
+//! The registry records what the world knows about each component type:
+//! its `ComponentId`, name, layout and type-erased operations.
+//! It lets the world work with components whose types are not known at compile time,
+//! for example when restoring a snapshot.
+
+use std::alloc::Layout;
+use std::collections::hash_map::{Entry, HashMap};
+#[cfg(feature = "serde")]
+use std::ops::Range;
+use std::sync::{RwLock, RwLockReadGuard};
+
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+
+/// Clones the component in a single row into a single row `ComponentStore`.
+pub(crate) type CloneFn = fn(&dyn ComponentVec, usize) -> Result<ComponentStore, Error>;
+
+/// Appends `count` clones of the first component in one column to the end of another column of the same type.
+pub(crate) type CloneIntoFn = fn(&dyn ComponentVec, &mut dyn ComponentVec, usize);
+
+/// Formats the component in a single row with its `Debug` implementation.
+pub(crate) type DebugFn = fn(&dyn ComponentVec, usize) -> Result<String, Error>;
+
+/// Creates a single row `ComponentStore` holding a default component.
+pub(crate) type DefaultFn = fn() -> ComponentStore;
+
+/// Serializes a range of rows in a column, in row order.
+#[cfg(feature = "serde")]
+pub(crate) type SerializeFn =
+    fn(&dyn ComponentVec, Range<usize>) -> Result<Vec<serde_json::Value>, Error>;
+
+/// Deserializes a single component into a single row `ComponentStore`.
+#[cfg(feature = "serde")]
+pub(crate) type DeserializeFn = fn(&serde_json::Value) -> Result<ComponentStore, Error>;
+
+/// Appends the raw bytes of every component in a column.
+pub(crate) type WritePodFn = fn(&dyn ComponentVec, &mut Vec<u8>) -> Result<(), Error>;
+
+/// Replaces every component in a column with `rows` components read from raw bytes.
+pub(crate) type ReadPodFn = fn(&mut dyn ComponentVec, &[u8], usize);
+
+/// Copies a plain-old-data component type to and from raw bytes.
+#[derive(Clone, Copy)]
+pub(crate) struct PodFns {
+    pub(crate) size: usize,
+    pub(crate) write: WritePodFn,
+    pub(crate) read: ReadPodFn,
+}
+
+/// Updates the `Entity` handles held by the component in a single row.
+pub(crate) type MapEntitiesFn = fn(&mut dyn ComponentVec, usize, &mut dyn FnMut(Entity) -> Entity);
+
+/// What the registry knows about a single component type.
+#[derive(Clone)]
+pub struct ComponentInfo {
+    pub(crate) id: ComponentId,
+    pub(crate) name: String,
+    pub(crate) layout: Layout,
+    pub(crate) clone: Option<CloneFn>,
+    pub(crate) clone_into: Option<CloneIntoFn>,
+    pub(crate) debug: Option<DebugFn>,
+    pub(crate) default: Option<DefaultFn>,
+    pub(crate) map_entities: Option<MapEntitiesFn>,
+    pub(crate) pod: Option<PodFns>,
+    #[cfg(feature = "serde")]
+    pub(crate) serialize: Option<SerializeFn>,
+    #[cfg(feature = "serde")]
+    pub(crate) deserialize: Option<DeserializeFn>,
+}
+
+impl ComponentInfo {
+    fn new<T: Component>(name: &str) -> Self {
+        Self::with_layout(ComponentId::of::<T>(), name, Layout::new::<T>())
+    }
+
+    fn with_layout(id: ComponentId, name: &str, layout: Layout) -> Self {
+        Self {
+            id,
+            name: name.to_string(),
+            layout,
+            clone: None,
+            clone_into: None,
+            debug: None,
+            default: None,
+            map_entities: None,
+            pod: None,
+            #[cfg(feature = "serde")]
+            serialize: None,
+            #[cfg(feature = "serde")]
+            deserialize: None,
+        }
+    }
+
+    /// Takes each operation this info lacks from another info for the same type.
+    fn fill_from(&mut self, other: &ComponentInfo) {
+        self.clone = self.clone.or(other.clone);
+        self.clone_into = self.clone_into.or(other.clone_into);
+        self.debug = self.debug.or(other.debug);
+        self.default = self.default.or(other.default);
+        self.map_entities = self.map_entities.or(other.map_entities);
+        self.pod = self.pod.or(other.pod);
+        #[cfg(feature = "serde")]
+        {
+            self.serialize = self.serialize.or(other.serialize);
+            self.deserialize = self.deserialize.or(other.deserialize);
+        }
+    }
+
+    pub fn id(&self) -> ComponentId {
+        self.id
+    }
+
+    /// The registered name, or the Rust type name if the type was never registered by name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The size and alignment of a single component.
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Clones the component in a single row, or returns `None` if the type cannot be cloned.
+    pub(crate) fn clone_component(
+        &self,
+        column: &dyn ComponentVec,
+        row: usize,
+    ) -> Option<Result<ComponentStore, Error>> {
+        let clone = self.clone?;
+        if !self.id.is_dynamic() {
+            return Some(clone(column, row));
+        }
+        // Every dynamic component shares one clone function, which knows neither its id nor its name.
+        Some(match clone(column, row) {
+            Ok(mut store) => {
+                store.id = self.id;
+                Ok(store)
+            }
+            Err(Error::ComponentAlreadyBorrowed(_)) => {
+                Err(ComponentAlreadyBorrowed::with_name(self.name.clone()).into())
+            }
+            Err(e) => Err(e),
+        })
+    }
+
+    /// Returns true if the type was registered with `register_clone` or is a dynamic component.
+    pub fn can_clone(&self) -> bool {
+        self.clone.is_some()
+    }
+
+    /// Returns true if the type was registered with `register_debug`.
+    pub fn can_debug(&self) -> bool {
+        self.debug.is_some()
+    }
+
+    /// Returns true if the type was registered with `register_default`.
+    pub fn can_default(&self) -> bool {
+        self.default.is_some()
+    }
+
+    /// Returns true if the type was registered with `register_serializable`.
+    #[cfg(feature = "serde")]
+    pub fn can_serialize(&self) -> bool {
+        self.serialize.is_some()
+    }
+}
+
+/// Maps component types to their names, layouts and type-erased operations.
+/// Every component type stored in a `World` is recorded here, even if it was never registered by name.
+#[derive(Default)]
+pub struct ComponentRegistry {
+    infos: HashMap<ComponentId, ComponentInfo>,
+    names: HashMap<String, ComponentId>,
+    dynamic_count: u32,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns what is known about a component type.
+    pub fn info(&self, id: ComponentId) -> Option<&ComponentInfo> {
+        self.infos.get(&id)
+    }
+
+    /// Iterates over every known component type in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &ComponentInfo> {
+        self.infos.values()
+    }
+
+    /// Returns the name a component type was registered with.
+    pub fn name_of(&self, id: ComponentId) -> Option<&str> {
+        self.infos.get(&id).map(|info| info.name.as_str())
+    }
+
+    /// Returns the component type registered under a name.
+    pub fn id_of(&self, name: &str) -> Option<ComponentId> {
+        self.names.get(name).copied()
+    }
+
+    /// Records a component type the world is about to store.
+    /// A type seen for the first time is named after its Rust type if that name is free.
+    pub(crate) fn register_type<T: Component>(&mut self) -> ComponentId {
+        let id = ComponentId::of::<T>();
+        if let Entry::Vacant(entry) = self.infos.entry(id) {
+            let name = std::any::type_name::<T>();
+            entry.insert(ComponentInfo::new::<T>(name));
+            self.names.entry(name.to_string()).or_insert(id);
+        }
+        id
+    }
+
+    /// Copies what another registry knows about a component type into this registry.
+    /// A type this registry has not seen keeps its name only if it is free here,
+    /// and a type it has seen gains any operations it is missing.
+    pub(crate) fn import(&mut self, other: &ComponentRegistry, id: ComponentId) {
+        if let Some(info) = other.info(id) {
+            self.import_type(info);
+        }
+    }
+
+    /// Makes a component type described by another registry known to this one, returning its id here.
+    /// Dynamic components are matched up by name since their ids differ between registries.
+    pub(crate) fn import_info(
+        &mut self,
+        info: &ComponentInfo,
+    ) -> Result<ComponentId, ComponentNameConflict> {
+        if info.id.is_dynamic() {
+            self.register_dynamic(&info.name, info.layout)
+        } else {
+            self.import_type(info);
+            Ok(info.id)
+        }
+    }
+
+    fn import_type(&mut self, info: &ComponentInfo) {
+        let id = info.id;
+        match self.infos.entry(id) {
+            Entry::Vacant(entry) => {
+                let mut info = info.clone();
+                if self.names.contains_key(&info.name) {
+                    info.name = format!("{:?}", id);
+                }
+                self.names.insert(info.name.clone(), id);
+                entry.insert(info);
+            }
+            Entry::Occupied(mut entry) => entry.get_mut().fill_from(info),
+        }
+    }
+
+    /// Registers a name for `T`, returning its info so operations can be attached to it.
+    /// Registering a type again keeps its operations but replaces its name.
+    /// An error is returned if the name is already registered to another type.
+    fn register<T: Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<&mut ComponentInfo, ComponentNameConflict> {
+        let id = ComponentId::of::<T>();
+        if self.names.get(name).is_some_and(|existing| *existing != id) {
+            return Err(ComponentNameConflict::new(name));
+        }
+
+        let info = self
+            .infos
+            .entry(id)
+            .or_insert_with(|| ComponentInfo::new::<T>(name));
+        if info.name != name {
+            if self.names.get(&info.name) == Some(&id) {
+                self.names.remove(&info.name);
+            }
+            info.name = name.to_string();
+        }
+        self.names.insert(name.to_string(), id);
+        Ok(info)
+    }
+
+    /// Lets `T`'s `Entity` handles be remapped without giving it a name,
+    /// for types the world registers itself and which cannot clash with a user's names.
+    pub(crate) fn add_map_entities<T: MapEntities + Component>(&mut self) {
+        let id = self.register_type::<T>();
+        self.infos.get_mut(&id).unwrap().map_entities = Some(map_entities_in_row::<T>);
+    }
+
+    /// Registers a component defined at runtime as a block of bytes with the given layout.
+    /// Registering the same name and layout again returns the same id,
+    /// and an error is returned if the name is already registered to another component.
+    ///
+    /// # Panics
+    /// Panics if the layout is aligned to more than 16 bytes.
+    pub fn register_dynamic(
+        &mut self,
+        name: &str,
+        layout: Layout,
+    ) -> Result<ComponentId, ComponentNameConflict> {
+        self.check_dynamic(name, layout)?;
+        if let Some(existing) = self.id_of(name) {
+            return Ok(existing);
+        }
+        assert!(
+            layout.align() <= MAX_DYNAMIC_ALIGN,
+            "Dynamic components cannot be aligned to more than {} bytes",
+            MAX_DYNAMIC_ALIGN
+        );
+
+        let id = ComponentId::dynamic(self.dynamic_count);
+        self.dynamic_count += 1;
+        let mut info = ComponentInfo::with_layout(id, name, layout);
+        // Dynamic components are plain bytes, so they can always be copied.
+        info.clone = Some(clone_dynamic_row);
+        info.clone_into = Some(clone_dynamic_into_column);
+        self.infos.insert(id, info);
+        self.names.insert(name.to_string(), id);
+        Ok(id)
+    }
+
+    /// Returns an error if `name` is already registered to anything
+    /// other than a dynamic component with the same layout.
+    pub(crate) fn check_dynamic(
+        &self,
+        name: &str,
+        layout: Layout,
+    ) -> Result<(), ComponentNameConflict> {
+        match self.id_of(name) {
+            Some(existing) if !existing.is_dynamic() || self.infos[&existing].layout != layout => {
+                Err(ComponentNameConflict::new(name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Registers `T` under `name` without attaching any operations.
+    pub fn register_component<T: Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<ComponentId, ComponentNameConflict> {
+        Ok(self.register::<T>(name)?.id)
+    }
+
+    /// Registers `T` under `name` so its components can be cloned without knowing its type.
+    pub fn register_clone<T: Clone + Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<(), ComponentNameConflict> {
+        let info = self.register::<T>(name)?;
+        info.clone = Some(clone_row::<T>);
+        info.clone_into = Some(clone_into_column::<T>);
+        Ok(())
+    }
+
+    /// Registers `T` under `name` so its components can be formatted without knowing its type.
+    pub fn register_debug<T: std::fmt::Debug + Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<(), ComponentNameConflict> {
+        self.register::<T>(name)?.debug = Some(debug_row::<T>);
+        Ok(())
+    }
+
+    /// Registers `T` under `name` so a default component can be created without knowing its type.
+    pub fn register_default<T: Default + Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<(), ComponentNameConflict> {
+        self.register::<T>(name)?.default = Some(default_component::<T>);
+        Ok(())
+    }
+
+    /// Registers `T` under `name` so it can be saved and loaded with serde.
+    #[cfg(feature = "serde")]
+    pub fn register_serializable<T>(&mut self, name: &str) -> Result<(), ComponentNameConflict>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + Component,
+    {
+        let info = self.register::<T>(name)?;
+        info.serialize = Some(serialize_rows::<T>);
+        info.deserialize = Some(deserialize_component::<T>);
+        Ok(())
+    }
+
+    /// Registers `T` under `name` as a plain-old-data component
+    /// that can be copied byte for byte into a rollback snapshot.
+    pub fn register_pod<T: bytemuck::Pod + Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<(), ComponentNameConflict> {
+        let info = self.register::<T>(name)?;
+        info.pod = Some(PodFns {
+            size: std::mem::size_of::<T>(),
+            write: write_pod_column::<T>,
+            read: read_pod_column::<T>,
+        });
+        Ok(())
+    }
+
+    /// Registers `T` under `name` as a component holding `Entity` handles
+    /// that must be updated when the entities are recreated elsewhere.
+    pub fn register_map_entities<T: MapEntities + Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<(), ComponentNameConflict> {
+        let info = self.register::<T>(name)?;
+        info.map_entities = Some(map_entities_in_row::<T>);
+        Ok(())
+    }
+}
+
+pub(crate) fn read_column<T: 'static>(
+    column: &dyn ComponentVec,
+) -> Result<RwLockReadGuard<'_, Vec<T>>, ComponentAlreadyBorrowed> {
+    column
+        .to_any()
+        .downcast_ref::<RwLock<Vec<T>>>()
+        .unwrap()
+        .try_read()
+        .map_err(|_| ComponentAlreadyBorrowed::new::<T>())
+}
+
+fn clone_row<T: Clone + Component>(
+    column: &dyn ComponentVec,
+    row: usize,
+) -> Result<ComponentStore, Error> {
+    Ok(ComponentStore::from_value(
+        read_column::<T>(column)?[row].clone(),
+    ))
+}
+
+pub(crate) fn clone_into_column<T: Clone + Component>(
+    source: &dyn ComponentVec,
+    destination: &mut dyn ComponentVec,
+    count: usize,
+) {
+    let source = read_column::<T>(source).unwrap();
+    let destination = component_vec_to_mut::<T>(destination);
+    destination.extend(std::iter::repeat_n(&source[0], count).cloned());
+}
+
+fn read_dynamic_column(
+    column: &dyn ComponentVec,
+) -> Result<RwLockReadGuard<'_, DynamicVec>, ComponentAlreadyBorrowed> {
+    column
+        .to_any()
+        .downcast_ref::<RwLock<DynamicVec>>()
+        .unwrap()
+        .try_read()
+        .map_err(|_| ComponentAlreadyBorrowed::with_name("dynamic component"))
+}
+
+fn clone_dynamic_row(column: &dyn ComponentVec, row: usize) -> Result<ComponentStore, Error> {
+    let mut data = column.new_same_type();
+    dynamic_vec_to_mut(&mut *data).push(read_dynamic_column(column)?.row(row));
+    Ok(ComponentStore {
+        id: ComponentId::dynamic(0),
+        data,
+    })
+}
+
+fn clone_dynamic_into_column(
+    source: &dyn ComponentVec,
+    destination: &mut dyn ComponentVec,
+    count: usize,
+) {
+    let source = read_dynamic_column(source).unwrap();
+    let destination = dynamic_vec_to_mut(destination);
+    for _ in 0..count {
+        destination.push(source.row(0));
+    }
+}
+
+fn debug_row<T: std::fmt::Debug + Component>(
+    column: &dyn ComponentVec,
+    row: usize,
+) -> Result<String, Error> {
+    Ok(format!("{:?}", read_column::<T>(column)?[row]))
+}
+
+fn default_component<T: Default + Component>() -> ComponentStore {
+    ComponentStore::from_value(T::default())
+}
+
+fn write_pod_column<T: bytemuck::Pod>(
+    column: &dyn ComponentVec,
+    bytes: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let column = read_column::<T>(column)?;
+    bytes.extend_from_slice(bytemuck::cast_slice(&column));
+    Ok(())
+}
+
+fn read_pod_column<T: bytemuck::Pod>(column: &mut dyn ComponentVec, bytes: &[u8], rows: usize) {
+    let column = component_vec_to_mut::<T>(column);
+    column.clear();
+    column.resize(rows, T::zeroed());
+    // The bytes may not be aligned for `T` so they are copied into the column's storage.
+    if std::mem::size_of::<T>() > 0 {
+        bytemuck::cast_slice_mut::<T, u8>(column).copy_from_slice(bytes);
+    }
+}
+
+fn map_entities_in_row<T: MapEntities + Component>(
+    column: &mut dyn ComponentVec,
+    row: usize,
+    map: &mut dyn FnMut(Entity) -> Entity,
+) {
+    component_vec_to_mut::<T>(column)[row].map_entities(map);
+}
+
+#[cfg(feature = "serde")]
+fn serialize_rows<T: serde::Serialize + 'static>(
+    column: &dyn ComponentVec,
+    rows: Range<usize>,
+) -> Result<Vec<serde_json::Value>, Error> {
+    let column = read_column::<T>(column)?;
+    column[rows]
+        .iter()
+        .map(|t| serde_json::to_value(t).map_err(Error::from))
+        .collect()
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_component<T: serde::de::DeserializeOwned + Component>(
+    value: &serde_json::Value,
+) -> Result<ComponentStore, Error> {
+    Ok(ComponentStore::from_value(T::deserialize(value)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::World;
+
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct Health(u32);
+
+    #[test]
+    fn test_registry_records_stored_types() {
+        let mut world = World::new();
+        let entity = world.spawn((Health(5), 1u8));
+
+        let id = ComponentId::of::<Health>();
+        let info = world.registry().info(id).unwrap();
+        assert_eq!(info.name(), std::any::type_name::<Health>());
+        assert_eq!(info.layout(), Layout::new::<Health>());
+        assert_eq!(info.id().type_id(), Some(std::any::TypeId::of::<Health>()));
+        assert!(!info.can_debug());
+
+        let mut ids = world.component_ids(entity).unwrap();
+        ids.sort();
+        let mut expected = vec![id, ComponentId::of::<u8>()];
+        expected.sort();
+        assert_eq!(ids, expected);
+
+        // Registering a name replaces the Rust type name.
+        world.register_debug::<Health>("Health").unwrap();
+        assert_eq!(world.registry().id_of("Health"), Some(id));
+        assert_eq!(
+            world.registry().id_of(std::any::type_name::<Health>()),
+            None
+        );
+        assert_eq!(world.registry().name_of(id), Some("Health"));
+    }
+
+    #[test]
+    fn test_registry_vtable() {
+        let mut world = World::new();
+        world.register_clone::<Health>("Health").unwrap();
+        world.register_debug::<Health>("Health").unwrap();
+        world.register_default::<Health>("Health").unwrap();
+        let id = world.registry().id_of("Health").unwrap();
+
+        let entity = world.spawn((1u8,));
+        assert!(matches!(
+            world.debug_component(entity, id),
+            Err(Error::EntityMissingComponent(_))
+        ));
+        world.add_default_component(entity, id).unwrap();
+        assert_eq!(
+            *world.get_component_mut::<Health>(entity).unwrap(),
+            Health(0)
+        );
+
+        world.get_component_mut::<Health>(entity).unwrap().0 = 3;
+        assert_eq!(world.debug_component(entity, id).unwrap(), "Health(3)");
+        assert!(matches!(
+            world.debug_component(entity, ComponentId::of::<u8>()),
+            Err(Error::ComponentNotRegistered(_))
+        ));
+
+        let archetype = &world.archetypes[world.entities[entity.index as usize]
+            .location
+            .archetype_index as usize];
+        let column = archetype.components.iter().find(|c| c.id == id).unwrap();
+        let clone = world.registry().info(id).unwrap().clone.unwrap();
+        let mut cloned = clone(&*column.data, 0).unwrap();
+        assert_eq!(
+            component_vec_to_mut::<Health>(&mut *cloned.data)[0],
+            Health(3)
+        );
+    }
+
+    #[test]
+    fn test_registry_name_conflict() {
+        let mut world = World::new();
+        world.register_clone::<Health>("Health").unwrap();
+        // Registering the same type again under its own name is fine.
+        world.register_debug::<Health>("Health").unwrap();
+
+        let error = world.register_clone::<u32>("Health").unwrap_err();
+        assert_eq!(error.name(), "Health");
+        assert!(world
+            .register_dynamic_component("Health", Layout::new::<u32>())
+            .is_err());
+        assert_eq!(
+            world.registry().id_of("Health"),
+            Some(ComponentId::of::<Health>())
+        );
+        assert_eq!(world.registry().name_of(ComponentId::of::<u32>()), None);
+    }
+}
@@ -0,0 +1,340 @@
+//! Relationships from one entity to others, such as `Likes`, `Owns` or `AttachedTo`.
+//!
+//! A relation is any component type `R` paired with a target entity.
+//! The source stores its relations of each kind in a `Relations<R>` component
+//! and every target stores the entities relating to it in a `RelationSources<R>` component.
+//! Both are only changed through `World::add_relation` and `World::remove_relation` so they always agree,
+//! and `World::despawn` cleans up both sides according to the relation's `OnTargetDespawn` policy.
+
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+use crate::world::*;
+
+/// The relations of kind `R` from this entity, one per target in the order they were added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Relations<R>(Vec<(Entity, R)>);
+
+impl<R> Relations<R> {
+    /// The entities this entity relates to.
+    pub fn targets(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.0.iter().map(|(target, _)| *target)
+    }
+
+    /// The relation to `target`, if there is one.
+    pub fn get(&self, target: Entity) -> Option<&R> {
+        self.0.iter().find(|(t, _)| *t == target).map(|(_, r)| r)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &R)> {
+        self.0.iter().map(|(target, relation)| (*target, relation))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<R> MapEntities for Relations<R> {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        for (target, _) in self.0.iter_mut() {
+            target.map_entities(map);
+        }
+    }
+}
+
+/// The entities with a relation of kind `R` to this entity, in the order they were added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RelationSources<R> {
+    sources: Vec<Entity>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    kind: std::marker::PhantomData<fn() -> R>,
+}
+
+impl<R> std::ops::Deref for RelationSources<R> {
+    type Target = [Entity];
+    fn deref(&self) -> &[Entity] {
+        &self.sources
+    }
+}
+
+impl<R> MapEntities for RelationSources<R> {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        for source in self.sources.iter_mut() {
+            source.map_entities(map);
+        }
+    }
+}
+
+/// What happens to the entities relating to a target when the target is despawned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnTargetDespawn {
+    /// The relation is removed from each source.
+    #[default]
+    RemoveRelation,
+    /// Each source is despawned as well.
+    DespawnSource,
+}
+
+/// Detaches a despawning entity from one relation kind,
+/// adding any sources its policy despawns with it to the list of entities still to despawn.
+pub(crate) type DetachFn = fn(&mut World, Entity, &mut Vec<Entity>);
+
+impl World {
+    /// Registers `R` as a relation kind with the given despawn policy.
+    /// Relations added before registering use `OnTargetDespawn::RemoveRelation`.
+    pub fn register_relation<R: Component>(&mut self, policy: OnTargetDespawn) {
+        self.relation_policies
+            .insert(ComponentId::of::<R>(), policy);
+        if self
+            .relation_hooks
+            .insert(ComponentId::of::<Relations<R>>(), detach_source::<R>)
+            .is_none()
+        {
+            self.relation_hooks
+                .insert(ComponentId::of::<RelationSources<R>>(), detach_target::<R>);
+            self.registry.add_map_entities::<Relations<R>>();
+            self.registry.add_map_entities::<RelationSources<R>>();
+        }
+    }
+
+    /// Adds a relation from `source` to `target`, replacing any existing relation of the same kind between them.
+    pub fn add_relation<R: Component>(
+        &mut self,
+        source: Entity,
+        relation: R,
+        target: Entity,
+    ) -> Result<(), Error> {
+        for entity in [source, target] {
+            if !self.contains(entity) {
+                return Err(NoSuchEntity::new(entity).into());
+            }
+        }
+        if !self
+            .relation_hooks
+            .contains_key(&ComponentId::of::<Relations<R>>())
+        {
+            self.register_relation::<R>(OnTargetDespawn::default());
+        }
+
+        if let Ok(relations) = self.get_component_mut::<Relations<R>>(source) {
+            if let Some((_, existing)) = relations.0.iter_mut().find(|(t, _)| *t == target) {
+                *existing = relation;
+                return Ok(());
+            }
+            relations.0.push((target, relation));
+        } else {
+            self.add_component(source, Relations(vec![(target, relation)]))?;
+        }
+        if let Ok(sources) = self.get_component_mut::<RelationSources<R>>(target) {
+            sources.sources.push(source);
+        } else {
+            self.add_component(
+                target,
+                RelationSources::<R> {
+                    sources: vec![source],
+                    kind: std::marker::PhantomData,
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Removes the relation of kind `R` from `source` to `target`, returning it if there was one.
+    pub fn remove_relation<R: Component>(
+        &mut self,
+        source: Entity,
+        target: Entity,
+    ) -> Result<Option<R>, NoSuchEntity> {
+        if !self.contains(source) {
+            return Err(NoSuchEntity::new(source));
+        }
+        let relation = self.remove_relation_entry::<R>(source, target);
+        if relation.is_some() {
+            self.remove_relation_source::<R>(target, source);
+        }
+        Ok(relation)
+    }
+
+    /// Returns true if `source` has a relation of kind `R` to `target`.
+    pub fn has_relation<R: Component>(&self, source: Entity, target: Entity) -> bool {
+        self.get::<Relations<R>>(source)
+            .is_ok_and(|relations| relations.get(target).is_some())
+    }
+
+    /// The entities `source` has a relation of kind `R` to.
+    pub fn targets<R: Component>(&self, source: Entity) -> Vec<Entity> {
+        self.get::<Relations<R>>(source)
+            .map(|relations| relations.targets().collect())
+            .unwrap_or_default()
+    }
+
+    /// The entities with a relation of kind `R` to `target`.
+    pub fn sources<R: Component>(&self, target: Entity) -> Vec<Entity> {
+        self.get::<RelationSources<R>>(target)
+            .map(|sources| sources.to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Removes a despawning entity from every relation it is the source or target of.
+    /// Sources that should be despawned along with it are added to `pending` rather than despawned here,
+    /// so a long chain of relations is despawned in a loop instead of recursing once per link.
+    pub(crate) fn detach_relations(&mut self, entity: Entity, pending: &mut Vec<Entity>) {
+        if self.relation_hooks.is_empty() {
+            return;
+        }
+        let hooks: Vec<DetachFn> = self
+            .component_ids(entity)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|id| self.relation_hooks.get(id).copied())
+            .collect();
+        for hook in hooks {
+            hook(self, entity, pending);
+        }
+    }
+
+    fn remove_relation_entry<R: Component>(&mut self, source: Entity, target: Entity) -> Option<R> {
+        let relations = self.get_component_mut::<Relations<R>>(source).ok()?;
+        let index = relations.0.iter().position(|(t, _)| *t == target)?;
+        let (_, relation) = relations.0.remove(index);
+        if relations.0.is_empty() {
+            let _ = self.remove_component::<Relations<R>>(source);
+        }
+        Some(relation)
+    }
+
+    fn remove_relation_source<R: Component>(&mut self, target: Entity, source: Entity) {
+        if let Ok(sources) = self.get_component_mut::<RelationSources<R>>(target) {
+            sources.sources.retain(|s| *s != source);
+            if sources.sources.is_empty() {
+                let _ = self.remove_component::<RelationSources<R>>(target);
+            }
+        }
+    }
+}
+
+fn detach_source<R: Component>(world: &mut World, source: Entity, _pending: &mut Vec<Entity>) {
+    if let Ok(relations) = world.remove_component::<Relations<R>>(source) {
+        for (target, _) in relations.0 {
+            world.remove_relation_source::<R>(target, source);
+        }
+    }
+}
+
+fn detach_target<R: Component>(world: &mut World, target: Entity, pending: &mut Vec<Entity>) {
+    // Removed before cascading so a cycle of relations cannot revisit this entity.
+    let Ok(sources) = world.remove_component::<RelationSources<R>>(target) else {
+        return;
+    };
+    let policy = world
+        .relation_policies
+        .get(&ComponentId::of::<R>())
+        .copied()
+        .unwrap_or_default();
+    for source in sources.sources {
+        world.remove_relation_entry::<R>(source, target);
+        if policy == OnTargetDespawn::DespawnSource {
+            pending.push(source);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Owns;
+    #[derive(Debug, PartialEq)]
+    struct Likes(u32);
+    struct AttachedTo;
+
+    #[test]
+    fn test_relations_add_and_query() {
+        let mut world = World::new();
+        let a = world.spawn((1u8,));
+        let b = world.spawn((2u8,));
+        let item = world.spawn((3u8,));
+
+        world.add_relation(a, Owns, item).unwrap();
+        world.add_relation(b, Owns, item).unwrap();
+        world.add_relation(a, Likes(1), b).unwrap();
+        world.add_relation(a, Likes(5), b).unwrap();
+        assert_eq!(world.sources::<Owns>(item), vec![a, b]);
+        assert_eq!(world.targets::<Owns>(a), vec![item]);
+        assert_eq!(world.targets::<Likes>(a), vec![b]);
+        assert_eq!(
+            world.get::<Relations<Likes>>(a).unwrap().get(b),
+            Some(&Likes(5))
+        );
+        assert!(world.has_relation::<Likes>(a, b));
+        assert!(!world.has_relation::<Likes>(b, a));
+
+        // Relations are components so they can be queried like any other.
+        let owners = world.query::<(&Relations<Owns>,)>().unwrap().iter().count();
+        assert_eq!(owners, 2);
+
+        assert_eq!(world.remove_relation::<Owns>(a, item).unwrap(), Some(Owns));
+        assert_eq!(world.remove_relation::<Owns>(a, item).unwrap(), None);
+        assert!(world.get::<Relations<Owns>>(a).is_err());
+        assert_eq!(world.sources::<Owns>(item), vec![b]);
+    }
+
+    #[test]
+    fn test_relations_despawn_policies() {
+        let mut world = World::new();
+        world.register_relation::<AttachedTo>(OnTargetDespawn::DespawnSource);
+        let a = world.spawn((1u8,));
+        let b = world.spawn((2u8,));
+        let ship = world.spawn((3u8,));
+        let turret = world.spawn((4u8,));
+        let barrel = world.spawn((5u8,));
+
+        world.add_relation(a, Likes(1), b).unwrap();
+        world.add_relation(b, Likes(2), a).unwrap();
+        world.add_relation(a, Owns, ship).unwrap();
+        world.add_relation(turret, AttachedTo, ship).unwrap();
+        world.add_relation(barrel, AttachedTo, turret).unwrap();
+        // A cycle must not prevent the cascade from finishing.
+        world.add_relation(ship, AttachedTo, barrel).unwrap();
+
+        // Removing a target removes the relation from its sources.
+        world.despawn(b).unwrap();
+        assert!(world.get::<Relations<Likes>>(a).is_err());
+        assert!(world.get::<RelationSources<Likes>>(a).is_err());
+
+        // Despawning a target despawns everything attached to it.
+        world.despawn(ship).unwrap();
+        assert!(!world.contains(turret));
+        assert!(!world.contains(barrel));
+        assert!(world.contains(a));
+        assert!(world.targets::<Owns>(a).is_empty());
+        assert!(world.get::<RelationSources<AttachedTo>>(a).is_err());
+    }
+
+    #[test]
+    fn test_relations_despawn_long_chain() {
+        let mut world = World::new();
+        world.register_relation::<AttachedTo>(OnTargetDespawn::DespawnSource);
+        let root = world.spawn((0u32,));
+        let mut last = root;
+        for i in 1..50_000 {
+            let link = world.spawn((i,));
+            world.add_relation(link, AttachedTo, last).unwrap();
+            last = link;
+        }
+
+        // Deep enough to overflow the stack if each link were despawned recursively.
+        world.despawn(root).unwrap();
+        assert!(!world.contains(last));
+        assert_eq!(world.query::<(&u32,)>().unwrap().iter().count(), 0);
+    }
+}
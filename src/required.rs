@@ -0,0 +1,170 @@
+//! Components that require other components to be present on the same entity.
+//!
+//! `World::register_required::<A, B>` makes `spawn` and `add_component` give every entity with an `A`
+//! a `B` as well, built by a constructor if it was not supplied.
+//! Requirements are followed transitively and the missing components are placed in the same
+//! archetype move as the components that required them.
+//! Entities can still end up without a requirement, for example if it is removed or was spawned before
+//! the requirement was registered, and `World::validate_requirements` lists them.
+
+use crate::component::*;
+use crate::entity::*;
+use crate::world::*;
+
+/// Builds a missing required component.
+pub(crate) type RequiredFn = Box<dyn Fn() -> ComponentStore + Send + Sync>;
+
+/// An entity that has a component but not one of the components it requires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequirementViolation {
+    pub entity: Entity,
+    /// The component that has the requirement.
+    pub component: ComponentId,
+    /// The required component the entity does not have.
+    pub missing: ComponentId,
+}
+
+impl World {
+    /// Makes every entity given an `A` by `spawn` or `add_component` also have a `B`,
+    /// using `constructor` to create it when it was not given as well.
+    /// Registering the same requirement again replaces its constructor.
+    pub fn register_required<A: Component, B: Component>(
+        &mut self,
+        constructor: impl Fn() -> B + Send + Sync + 'static,
+    ) {
+        self.registry.register_type::<A>();
+        self.registry.register_type::<B>();
+        let required_id = ComponentId::of::<B>();
+        let constructor: RequiredFn = Box::new(move || ComponentStore::from_value(constructor()));
+        let required = self.required.entry(ComponentId::of::<A>()).or_default();
+        if let Some(existing) = required.iter_mut().find(|(id, _)| *id == required_id) {
+            existing.1 = constructor;
+        } else {
+            required.push((required_id, constructor));
+        }
+    }
+
+    /// The components an entity with an `A` is required to have, not counting their own requirements.
+    pub fn required_components<A: Component>(&self) -> Vec<ComponentId> {
+        self.required
+            .get(&ComponentId::of::<A>())
+            .map(|required| required.iter().map(|(id, _)| *id).collect())
+            .unwrap_or_default()
+    }
+
+    /// Lists every entity that is missing a component required by one of its components.
+    pub fn validate_requirements(&self) -> Vec<RequirementViolation> {
+        let mut violations = Vec::new();
+        if self.required.is_empty() {
+            return violations;
+        }
+        for archetype in self.archetypes.iter() {
+            let has = |id: &ComponentId| archetype.components.iter().any(|c| c.id == *id);
+            for c in archetype.components.iter() {
+                let Some(required) = self.required.get(&c.id) else {
+                    continue;
+                };
+                for (missing, _) in required.iter().filter(|(id, _)| !has(id)) {
+                    violations.extend(archetype.entities.iter().map(|index| {
+                        RequirementViolation {
+                            entity: Entity {
+                                index: *index,
+                                generation: self.entities[*index as usize].generation,
+                            },
+                            component: c.id,
+                            missing: *missing,
+                        }
+                    }));
+                }
+            }
+        }
+        violations
+    }
+
+    /// Builds every component required directly or indirectly by `ids` that is not already among them.
+    pub(crate) fn required_stores(&self, ids: &[ComponentId]) -> Vec<ComponentStore> {
+        let mut stores = Vec::new();
+        if self.required.is_empty() {
+            return stores;
+        }
+        let mut present = ids.to_vec();
+        let mut i = 0;
+        while i < present.len() {
+            for (id, constructor) in self.required.get(&present[i]).into_iter().flatten() {
+                if !present.contains(id) {
+                    present.push(*id);
+                    stores.push(constructor());
+                }
+            }
+            i += 1;
+        }
+        stores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct RigidBody;
+    #[derive(Debug, Default, PartialEq)]
+    struct Velocity(f32);
+    #[derive(Debug, PartialEq)]
+    struct Mass(f32);
+    #[derive(Debug, Default, PartialEq)]
+    struct Inertia(f32);
+
+    fn physics_world() -> World {
+        let mut world = World::new();
+        world.register_required::<RigidBody, Velocity>(Velocity::default);
+        world.register_required::<RigidBody, Mass>(|| Mass(1.0));
+        world.register_required::<Mass, Inertia>(Inertia::default);
+        world
+    }
+
+    #[test]
+    fn test_required_components_inserted() {
+        let mut world = physics_world();
+        let a = world.spawn((RigidBody,));
+        assert_eq!(*world.get::<Velocity>(a).unwrap(), Velocity(0.0));
+        assert_eq!(*world.get::<Mass>(a).unwrap(), Mass(1.0));
+        assert_eq!(*world.get::<Inertia>(a).unwrap(), Inertia(0.0));
+
+        // Components that were given are kept.
+        let b = world.spawn((RigidBody, Mass(5.0)));
+        assert_eq!(*world.get::<Mass>(b).unwrap(), Mass(5.0));
+        assert_eq!(world.component_ids(b).unwrap().len(), 4);
+
+        let c = world.spawn((Velocity(2.0),));
+        world.add_component(c, RigidBody).unwrap();
+        assert_eq!(*world.get::<Velocity>(c).unwrap(), Velocity(2.0));
+        assert_eq!(*world.get::<Inertia>(c).unwrap(), Inertia(0.0));
+        assert!(world.validate_requirements().is_empty());
+    }
+
+    #[test]
+    fn test_required_components_validation() {
+        let mut world = World::new();
+        let a = world.spawn((RigidBody, Velocity(1.0)));
+        world.register_required::<RigidBody, Velocity>(Velocity::default);
+        world.register_required::<RigidBody, Mass>(|| Mass(1.0));
+        assert_eq!(
+            world.validate_requirements(),
+            vec![RequirementViolation {
+                entity: a,
+                component: ComponentId::of::<RigidBody>(),
+                missing: ComponentId::of::<Mass>(),
+            }]
+        );
+
+        world.add_component(a, Mass(2.0)).unwrap();
+        assert!(world.validate_requirements().is_empty());
+        world.remove_component::<Velocity>(a).unwrap();
+        assert_eq!(world.validate_requirements().len(), 1);
+        assert_eq!(
+            world.validate_requirements()[0].missing,
+            ComponentId::of::<Velocity>()
+        );
+    }
+}
@@ -0,0 +1,440 @@
+//! Fast binary snapshots for rollback networking.
+//!
+//! Every component type in the world must be registered with `World::register_pod`.
+//! Columns are copied byte for byte, so a snapshot can only be restored into the world it was taken
+//! from (or one built identically) and only while every archetype in the snapshot still exists.
+//! A snapshot ends with a checksum of its contents which is verified before anything is restored.
+//!
+//! The layout is:
+//! - The number of entity slots, then each slot's generation, location and whether it is alive.
+//! - The free list.
+//! - The number of archetypes, then for each archetype its bundle id, its entities
+//!   and the bytes of each of its columns.
+//! - The checksum.
+
+use std::collections::HashSet;
+
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+use crate::registry::*;
+use crate::world::*;
+
+/// A binary copy of every entity and component in a `World`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollbackSnapshot {
+    bytes: Vec<u8>,
+}
+
+impl RollbackSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The snapshot's bytes, including the trailing checksum.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Wraps bytes previously returned by `as_bytes`, checking they are intact.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, Error> {
+        let snapshot = Self { bytes };
+        snapshot.contents()?;
+        Ok(snapshot)
+    }
+
+    /// Returns the bytes before the checksum if the checksum matches them.
+    fn contents(&self) -> Result<&[u8], InvalidSnapshot> {
+        let split = self
+            .bytes
+            .len()
+            .checked_sub(8)
+            .ok_or_else(|| InvalidSnapshot::new("the data is truncated"))?;
+        let (contents, checksum) = self.bytes.split_at(split);
+        if fxhash::hash64(contents).to_le_bytes() == checksum {
+            Ok(contents)
+        } else {
+            Err(InvalidSnapshot::new("the checksum does not match"))
+        }
+    }
+}
+
+impl World {
+    /// Captures every entity and component in the world.
+    /// An error is returned if a component type has not been registered with `register_pod`
+    /// or if a component is currently mutably borrowed.
+    pub fn rollback_snapshot(&self) -> Result<RollbackSnapshot, Error> {
+        let mut snapshot = RollbackSnapshot::new();
+        self.rollback_snapshot_into(&mut snapshot)?;
+        Ok(snapshot)
+    }
+
+    /// Like `rollback_snapshot` but reuses the memory of an old snapshot.
+    pub fn rollback_snapshot_into(&self, snapshot: &mut RollbackSnapshot) -> Result<(), Error> {
+        let bytes = &mut snapshot.bytes;
+        bytes.clear();
+
+        write_len(bytes, self.entities.len());
+        for entity_info in self.entities.iter() {
+            write_entity_id(bytes, entity_info.generation);
+            write_entity_id(bytes, entity_info.location.archetype_index);
+            write_entity_id(bytes, entity_info.location.index_in_archetype);
+            bytes.push(entity_info.alive as u8);
+        }
+
+        write_len(bytes, self.free_entities.len());
+        for index in self.free_entities.iter() {
+            write_entity_id(bytes, *index);
+        }
+
+        write_len(bytes, self.archetypes.len());
+        for archetype in self.archetypes.iter() {
+            let ids: Vec<ComponentId> = archetype.components.iter().map(|c| c.id).collect();
+            bytes.extend_from_slice(&calculate_bundle_id(&ids).to_le_bytes());
+
+            write_len(bytes, archetype.entities.len());
+            for index in archetype.entities.iter() {
+                write_entity_id(bytes, *index);
+            }
+
+            for c in archetype.components.iter() {
+                let pod = self
+                    .pod_fns(c.id)
+                    .ok_or_else(|| ComponentNotRegistered::new(self.component_name(c.id)))?;
+                write_len(bytes, pod.size * archetype.entities.len());
+                (pod.write)(&*c.data, bytes)?;
+            }
+        }
+
+        let checksum = fxhash::hash64(&bytes[..]);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        Ok(())
+    }
+
+    /// Restores every entity and component from a snapshot taken with `rollback_snapshot`.
+    /// Archetypes created after the snapshot was taken are emptied.
+    /// If an error is returned the world is left unchanged.
+    pub fn rollback(&mut self, snapshot: &RollbackSnapshot) -> Result<(), Error> {
+        // Read and check everything before changing the world.
+        let mut reader = Reader(snapshot.contents()?);
+
+        // Counts are bounded by the bytes left so a corrupt or crafted count cannot cause a huge allocation.
+        let entity_size = std::mem::size_of::<EntityId>();
+        let entity_count = reader.count(3 * entity_size + 1)?;
+        let mut entities = Vec::with_capacity(entity_count);
+        for _ in 0..entity_count {
+            entities.push(EntityInfo {
+                generation: reader.entity_id()?,
+                location: EntityLocation {
+                    archetype_index: reader.entity_id()?,
+                    index_in_archetype: reader.entity_id()?,
+                },
+                alive: reader.take(1)?[0] != 0,
+            });
+        }
+
+        let free_count = reader.count(entity_size)?;
+        let mut free_entities = Vec::with_capacity(free_count);
+        for _ in 0..free_count {
+            free_entities.push(reader.entity_id()?);
+        }
+
+        let archetype_count = reader.len()?;
+        if archetype_count > self.archetypes.len() {
+            return Err(
+                InvalidSnapshot::new("the snapshot has archetypes this world does not").into(),
+            );
+        }
+
+        let mut archetypes = Vec::with_capacity(archetype_count);
+        for archetype in self.archetypes[..archetype_count].iter() {
+            let ids: Vec<ComponentId> = archetype.components.iter().map(|c| c.id).collect();
+            if reader.u64()? != calculate_bundle_id(&ids) {
+                return Err(InvalidSnapshot::new("the archetype layout has changed").into());
+            }
+
+            let rows = reader.count(entity_size)?;
+            let archetype_entities: Vec<EntityId> = reader
+                .take(rows * entity_size)?
+                .chunks_exact(entity_size)
+                .map(|b| EntityId::from_le_bytes(b.try_into().unwrap()))
+                .collect();
+
+            let mut columns = Vec::with_capacity(archetype.components.len());
+            for c in archetype.components.iter() {
+                let pod = self
+                    .pod_fns(c.id)
+                    .ok_or_else(|| ComponentNotRegistered::new(self.component_name(c.id)))?;
+                let len = reader.len()?;
+                if Some(len) != pod.size.checked_mul(rows) {
+                    return Err(InvalidSnapshot::new("a column has the wrong size").into());
+                }
+                columns.push((pod.read, reader.take(len)?));
+            }
+            archetypes.push((rows, archetype_entities, columns));
+        }
+
+        if !reader.0.is_empty() {
+            return Err(InvalidSnapshot::new("the data has trailing bytes").into());
+        }
+        check_locations(&entities, &free_entities, &archetypes)?;
+
+        for (archetype, (rows, archetype_entities, columns)) in
+            self.archetypes.iter_mut().zip(archetypes)
+        {
+            archetype.entities = archetype_entities;
+            for (c, (read, bytes)) in archetype.components.iter_mut().zip(columns) {
+                read(&mut *c.data, bytes, rows);
+            }
+        }
+
+        for archetype in self.archetypes[archetype_count..].iter_mut() {
+            archetype.entities.clear();
+            for c in archetype.components.iter_mut() {
+                c.data.clear();
+            }
+        }
+
+        self.entities = entities;
+        self.free_entities = free_entities;
+        self.mark_indices_stale();
+        Ok(())
+    }
+
+    fn pod_fns(&self, id: ComponentId) -> Option<PodFns> {
+        self.registry.info(id)?.pod
+    }
+}
+
+/// Checks that every live entity's location points at a row holding that entity and that every row holds a live entity,
+/// so a snapshot that passes cannot leave the world's entities and archetypes out of step.
+fn check_locations<C>(
+    entities: &[EntityInfo],
+    free_entities: &[EntityId],
+    archetypes: &[(usize, Vec<EntityId>, C)],
+) -> Result<(), InvalidSnapshot> {
+    let invalid = || InvalidSnapshot::new("an entity's location does not match the archetypes");
+    for entity_info in entities.iter().filter(|entity_info| entity_info.alive) {
+        let (_, archetype_entities, _) = archetypes
+            .get(entity_info.location.archetype_index as usize)
+            .ok_or_else(invalid)?;
+        archetype_entities
+            .get(entity_info.location.index_in_archetype as usize)
+            .ok_or_else(invalid)?;
+    }
+    for (archetype_index, (_, archetype_entities, _)) in archetypes.iter().enumerate() {
+        for (row, index) in archetype_entities.iter().enumerate() {
+            let entity_info = entities.get(*index as usize).ok_or_else(invalid)?;
+            if !entity_info.alive
+                || entity_info.location.archetype_index as usize != archetype_index
+                || entity_info.location.index_in_archetype as usize != row
+            {
+                return Err(invalid());
+            }
+        }
+    }
+    let mut seen = HashSet::new();
+    for index in free_entities.iter() {
+        if entities
+            .get(*index as usize)
+            .is_none_or(|entity_info| entity_info.alive)
+        {
+            return Err(InvalidSnapshot::new("the free list holds a live entity"));
+        }
+        // A repeated slot would be handed out to two entities.
+        if !seen.insert(*index) {
+            return Err(InvalidSnapshot::new("the free list repeats an entity slot"));
+        }
+    }
+    Ok(())
+}
+
+fn write_len(bytes: &mut Vec<u8>, len: usize) {
+    bytes.extend_from_slice(&(len as u64).to_le_bytes());
+}
+
+fn write_entity_id(bytes: &mut Vec<u8>, id: EntityId) {
+    bytes.extend_from_slice(&id.to_le_bytes());
+}
+
+/// Reads values written by `rollback_snapshot_into` from the front of a byte slice.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], InvalidSnapshot> {
+        if len > self.0.len() {
+            return Err(InvalidSnapshot::new("the data is truncated"));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u64(&mut self) -> Result<u64, InvalidSnapshot> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, InvalidSnapshot> {
+        usize::try_from(self.u64()?).map_err(|_| InvalidSnapshot::new("a length is too large"))
+    }
+
+    /// Reads the number of elements that follow, checking that there are enough bytes left for them.
+    fn count(&mut self, element_size: usize) -> Result<usize, InvalidSnapshot> {
+        let count = self.len()?;
+        if count > self.remaining() / element_size {
+            return Err(InvalidSnapshot::new("the data is truncated"));
+        }
+        Ok(count)
+    }
+
+    fn remaining(&self) -> usize {
+        self.0.len()
+    }
+
+    fn entity_id(&mut self) -> Result<EntityId, InvalidSnapshot> {
+        let bytes = self.take(std::mem::size_of::<EntityId>())?;
+        Ok(EntityId::from_le_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+    #[repr(C)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+    #[repr(C)]
+    struct Health(u32);
+
+    fn registered_world() -> World {
+        let mut world = World::new();
+        world.register_pod::<Position>("Position").unwrap();
+        world.register_pod::<Health>("Health").unwrap();
+        world
+    }
+
+    #[test]
+    fn test_rollback_round_trip() {
+        let mut world = registered_world();
+        let a = world.spawn((Position { x: 1.0, y: 2.0 }, Health(10)));
+        let b = world.spawn((Position { x: 3.0, y: 4.0 },));
+        let snapshot = world.rollback_snapshot().unwrap();
+
+        // Change the world in every way a frame might.
+        world.get_component_mut::<Position>(a).unwrap().x = 100.0;
+        world.despawn(b).unwrap();
+        let c = world.spawn((Health(5),));
+        world.remove_component::<Health>(a).unwrap();
+
+        world.rollback(&snapshot).unwrap();
+        assert!(world.contains(a));
+        assert!(world.contains(b));
+        assert!(!world.contains(c));
+        assert_eq!(
+            *world.get_component_mut::<Position>(a).unwrap(),
+            Position { x: 1.0, y: 2.0 }
+        );
+        assert_eq!(*world.get_component_mut::<Health>(a).unwrap(), Health(10));
+        assert_eq!(
+            *world.get_component_mut::<Position>(b).unwrap(),
+            Position { x: 3.0, y: 4.0 }
+        );
+    }
+
+    #[test]
+    fn test_rollback_rejects_corruption() {
+        let mut world = registered_world();
+        let a = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let b = world.spawn((Position { x: 3.0, y: 4.0 },));
+        let c = world.spawn((Position { x: 5.0, y: 6.0 },));
+        world.despawn(b).unwrap();
+        world.despawn(c).unwrap();
+        let snapshot = world.rollback_snapshot().unwrap();
+
+        let mut bytes = snapshot.as_bytes().to_vec();
+        bytes[10] ^= 1;
+        assert!(matches!(
+            RollbackSnapshot::from_bytes(bytes.clone()),
+            Err(Error::InvalidSnapshot(_))
+        ));
+        assert!(matches!(
+            world.rollback(&RollbackSnapshot { bytes }),
+            Err(Error::InvalidSnapshot(_))
+        ));
+
+        world.despawn(a).unwrap();
+        let mut other = registered_world();
+        assert!(other.rollback(&snapshot).is_err());
+        assert!(RollbackSnapshot::from_bytes(snapshot.as_bytes().to_vec()).is_ok());
+    }
+
+    #[test]
+    fn test_rollback_rejects_crafted_data() {
+        // The checksum only catches accidents, so crafted data arrives with a valid one.
+        fn with_checksum(mut contents: Vec<u8>) -> RollbackSnapshot {
+            let checksum = fxhash::hash64(&contents[..]);
+            contents.extend_from_slice(&checksum.to_le_bytes());
+            RollbackSnapshot::from_bytes(contents).unwrap()
+        }
+
+        let mut world = registered_world();
+        let a = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let b = world.spawn((Position { x: 3.0, y: 4.0 },));
+        let c = world.spawn((Position { x: 5.0, y: 6.0 },));
+        world.despawn(b).unwrap();
+        world.despawn(c).unwrap();
+        let snapshot = world.rollback_snapshot().unwrap();
+        let contents = snapshot.contents().unwrap().to_vec();
+
+        // A huge entity count.
+        let mut huge = contents.clone();
+        huge[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            world.rollback(&with_checksum(huge)),
+            Err(Error::InvalidSnapshot(_))
+        ));
+
+        // An entity whose location points past the end of its archetype.
+        let entity_size = std::mem::size_of::<EntityId>();
+        let mut moved = contents.clone();
+        let row = 8 + 2 * entity_size;
+        moved[row..row + entity_size].copy_from_slice(&(5 as EntityId).to_le_bytes());
+        assert!(matches!(
+            world.rollback(&with_checksum(moved)),
+            Err(Error::InvalidSnapshot(_))
+        ));
+
+        // A free list that would hand out the same slot twice.
+        let free = 8 + 3 * (3 * entity_size + 1) + 8;
+        let mut repeated = contents.clone();
+        let first = repeated[free..free + entity_size].to_vec();
+        repeated[free + entity_size..free + 2 * entity_size].copy_from_slice(&first);
+        assert!(matches!(
+            world.rollback(&with_checksum(repeated)),
+            Err(Error::InvalidSnapshot(_))
+        ));
+
+        // The world is unchanged by the failed rollbacks.
+        assert_eq!(
+            *world.get_component_mut::<Position>(a).unwrap(),
+            Position { x: 1.0, y: 2.0 }
+        );
+        world.rollback(&with_checksum(contents)).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_unregistered_component() {
+        let mut world = registered_world();
+        world.spawn((Position { x: 1.0, y: 2.0 }, String::new()));
+        assert!(matches!(
+            world.rollback_snapshot(),
+            Err(Error::ComponentNotRegistered(_))
+        ));
+    }
+}
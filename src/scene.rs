@@ -0,0 +1,227 @@
+//! Human-readable scene files.
+//!
+//! A scene is a list of entities, each with components keyed by their registered name.
+//! Unlike a `WorldSnapshot` a scene is spawned into an existing world alongside the entities
+//! already there, so every entity gets a fresh handle. `Entity` fields inside components
+//! registered with `World::register_map_entities` are remapped to the new handles.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::entity::*;
+use crate::error::*;
+use crate::world::*;
+
+/// A set of entities that can be written to and read from RON or JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub entities: Vec<SceneEntity>,
+}
+
+/// An entity within a `Scene`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneEntity {
+    /// Identifies the entity within the scene.
+    /// Components refer to other entities in the scene by this handle.
+    pub entity: Entity,
+    /// The entity's components keyed by their registered name.
+    #[serde(default)]
+    pub components: BTreeMap<String, serde_json::Value>,
+}
+
+impl Scene {
+    pub fn from_ron(text: &str) -> Result<Self, Error> {
+        Ok(ron::from_str(text)?)
+    }
+
+    pub fn to_ron(&self) -> Result<String, Error> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl World {
+    /// Spawns every entity in the scene and returns a map from the scene's handles to the new ones.
+    /// `Entity` handles inside components that refer to entities outside the scene
+    /// are replaced with `Entity::PLACEHOLDER`.
+    /// If an error is returned nothing is spawned.
+    pub fn spawn_scene(&mut self, scene: &Scene) -> Result<HashMap<Entity, Entity>, Error> {
+        // Deserialize everything up front so a failure leaves the world untouched.
+        let mut rows = Vec::with_capacity(scene.entities.len());
+        for scene_entity in scene.entities.iter() {
+            rows.push(self.deserialize_components(&scene_entity.components)?);
+        }
+
+        let mut entity_map = HashMap::with_capacity(rows.len());
+        for (scene_entity, stores) in scene.entities.iter().zip(rows) {
+            let entity = self.reserve_entity();
+            self.spawn_stores(entity.index, stores);
+            entity_map.insert(scene_entity.entity, entity);
+        }
+
+        let mut map = |entity| *entity_map.get(&entity).unwrap_or(&Entity::PLACEHOLDER);
+        for entity in entity_map.values() {
+            self.map_entities(*entity, &mut map);
+        }
+        Ok(entity_map)
+    }
+
+    /// Writes the given entities and their registered components to a scene.
+    /// The entities keep their current handles within the scene.
+    pub fn export_scene(&self, entities: &[Entity]) -> Result<Scene, Error> {
+        let mut scene = Scene::default();
+        for entity in entities.iter() {
+            let entity_info = self
+                .entity_info(*entity)
+                .ok_or_else(|| NoSuchEntity::new(*entity))?;
+            let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+            let row = entity_info.location.index_in_archetype as usize;
+
+            let mut components = BTreeMap::new();
+            for c in archetype.components.iter() {
+                let (name, serialize) = self
+                    .registry
+                    .info(c.id)
+                    .and_then(|info| Some((&info.name, info.serialize?)))
+                    .ok_or_else(|| ComponentNotRegistered::new(self.component_name(c.id)))?;
+                let value = serialize(&*c.data, row..row + 1)?.pop().unwrap();
+                components.insert(name.clone(), value);
+            }
+
+            scene.entities.push(SceneEntity {
+                entity: *entity,
+                components,
+            });
+        }
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Follow(Entity);
+
+    impl MapEntities for Follow {
+        fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+            self.0.map_entities(map);
+        }
+    }
+
+    fn registered_world() -> World {
+        let mut world = World::new();
+        world.register_serializable::<Position>("Position").unwrap();
+        world.register_serializable::<Follow>("Follow").unwrap();
+        world.register_map_entities::<Follow>("Follow").unwrap();
+        world
+    }
+
+    const SCENE: &str = r#"(
+        entities: [
+            (
+                entity: "0v0",
+                components: {
+                    "Position": (x: 1.0, y: 2.0),
+                },
+            ),
+            (
+                entity: "1v0",
+                components: {
+                    "Position": (x: 3.0, y: 4.0),
+                    "Follow": "0v0",
+                },
+            ),
+            (
+                entity: "2v0",
+                components: {
+                    "Follow": "7v0",
+                },
+            ),
+        ],
+    )"#;
+
+    #[test]
+    fn test_scene_spawn_remaps_entities() {
+        let mut world = registered_world();
+        // Occupy the slots the scene's handles would otherwise collide with.
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+        world.spawn((Position { x: 0.0, y: 0.0 },));
+
+        let scene = Scene::from_ron(SCENE).unwrap();
+        let entity_map = world.spawn_scene(&scene).unwrap();
+        let scene_entity = |index| Entity {
+            index,
+            generation: 0,
+        };
+        let leader = entity_map[&scene_entity(0)];
+        let follower = entity_map[&scene_entity(1)];
+        let stray = entity_map[&scene_entity(2)];
+
+        assert_eq!(
+            world.get_component_mut::<Follow>(follower).unwrap().0,
+            leader
+        );
+        assert_eq!(
+            *world.get_component_mut::<Position>(leader).unwrap(),
+            Position { x: 1.0, y: 2.0 }
+        );
+        assert_eq!(
+            world.get_component_mut::<Follow>(stray).unwrap().0,
+            Entity::PLACEHOLDER
+        );
+    }
+
+    #[test]
+    fn test_scene_export_round_trip() {
+        let mut world = registered_world();
+        let leader = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let follower = world.spawn((Position { x: 3.0, y: 4.0 }, Follow(leader)));
+        world.spawn((Position { x: 5.0, y: 6.0 },));
+
+        let scene = world.export_scene(&[leader, follower]).unwrap();
+        let text = scene.to_json().unwrap();
+        assert_eq!(Scene::from_json(&text).unwrap(), scene);
+        assert_eq!(Scene::from_ron(&scene.to_ron().unwrap()).unwrap(), scene);
+
+        let mut other = registered_world();
+        other.spawn((Position { x: 0.0, y: 0.0 },));
+        let entity_map = other.spawn_scene(&scene).unwrap();
+        assert_eq!(
+            other
+                .get_component_mut::<Follow>(entity_map[&follower])
+                .unwrap()
+                .0,
+            entity_map[&leader]
+        );
+    }
+
+    #[test]
+    fn test_scene_unknown_component() {
+        let mut world = World::new();
+        let scene = Scene::from_ron(SCENE).unwrap();
+        assert!(matches!(
+            world.spawn_scene(&scene),
+            Err(Error::ComponentNotRegistered(_))
+        ));
+        assert!(world.entities.is_empty());
+    }
+}
@@ -0,0 +1,665 @@
+//! Systems and the schedule that runs them in order.
+//!
+//! A system is a function whose parameters are `SystemParameter`s, such as a `Query` or a `&T`
+//! borrowing the only instance of a component, fetched from the world each time it runs.
+//! Systems only borrow the world immutably so they cannot spawn, despawn, add or remove components.
+//! Work that must change the world's structure straight away goes in an exclusive system,
+//! which takes `&mut World` and acts as a barrier: every system ordered before it has finished when it
+//! runs, and every system ordered after it sees its changes.
+//!
+//! Systems can be grouped into named sets. A set's run conditions and ordering constraints,
+//! given with `Schedule::configure_set`, apply to every system in it, so for example
+//! all gameplay systems can be turned off while the game is paused with a single condition.
+
+use crate::component::*;
+use crate::error::*;
+use crate::query::*;
+use crate::world::*;
+
+/// A function that can be run with parameters fetched from a `World`.
+/// It is implemented for functions of up to 8 `SystemParameter`s.
+pub trait System<P>: Send + Sync + 'static {
+    fn run(&mut self, world: &World) -> Result<(), FetchError>;
+}
+
+macro_rules! system_impl {
+    ($($name: ident),*) => {
+        impl<FUNC, $($name: SystemParameter),*> System<($($name,)*)> for FUNC
+        where
+            FUNC: Send + Sync + 'static,
+            FUNC: FnMut($($name,)*)
+                + for<'a, 'b> FnMut($(<<$name::Fetch as Fetch<'a>>::Item as FetchItem<'b>>::InnerItem,)*),
+        {
+            #[allow(non_snake_case, unused_variables)]
+            fn run(&mut self, world: &World) -> Result<(), FetchError> {
+                $(let mut $name = <$name::Fetch as Fetch>::fetch(world)?;)*
+                self($($name.inner(),)*);
+                Ok(())
+            }
+        }
+    };
+}
+
+system_impl! {}
+system_impl! {A}
+system_impl! {A, B}
+system_impl! {A, B, C}
+system_impl! {A, B, C, D}
+system_impl! {A, B, C, D, E}
+system_impl! {A, B, C, D, E, F}
+system_impl! {A, B, C, D, E, F, G}
+system_impl! {A, B, C, D, E, F, G, H}
+
+type SystemFn = Box<dyn FnMut(&World) -> Result<(), Error> + Send + Sync>;
+type ExclusiveSystemFn = Box<dyn FnMut(&mut World) -> Result<(), Error> + Send + Sync>;
+type Condition = Box<dyn FnMut(&World) -> bool + Send + Sync>;
+
+enum Step {
+    System(SystemFn),
+    Exclusive(ExclusiveSystemFn),
+}
+
+/// A system along with the sets it belongs to, its run conditions and its ordering constraints.
+pub struct SystemConfig {
+    step: Step,
+    sets: Vec<String>,
+    conditions: Vec<Condition>,
+    before: Vec<String>,
+    after: Vec<String>,
+}
+
+impl SystemConfig {
+    fn new(step: Step) -> Self {
+        Self {
+            step,
+            sets: Vec::new(),
+            conditions: Vec::new(),
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+
+    /// Configures a system with exclusive access to the world.
+    pub fn exclusive(mut system: impl FnMut(&mut World) + Send + Sync + 'static) -> Self {
+        Self::new(Step::Exclusive(Box::new(move |world| {
+            system(world);
+            Ok(())
+        })))
+    }
+
+    /// Configures an exclusive system that can fail, such as a nested schedule.
+    pub(crate) fn fallible_exclusive(
+        system: impl FnMut(&mut World) -> Result<(), Error> + Send + Sync + 'static,
+    ) -> Self {
+        Self::new(Step::Exclusive(Box::new(system)))
+    }
+
+    /// Only runs the system when `condition` returns true.
+    pub fn run_if(mut self, condition: impl FnMut(&World) -> bool + Send + Sync + 'static) -> Self {
+        self.conditions.push(Box::new(condition));
+        self
+    }
+
+    /// Adds the system to a named set, giving it the set's conditions and ordering.
+    pub fn in_set(mut self, set: impl Into<String>) -> Self {
+        self.sets.push(set.into());
+        self
+    }
+
+    /// Runs the system before every system in a set.
+    pub fn before(mut self, set: impl Into<String>) -> Self {
+        self.before.push(set.into());
+        self
+    }
+
+    /// Runs the system after every system in a set.
+    pub fn after(mut self, set: impl Into<String>) -> Self {
+        self.after.push(set.into());
+        self
+    }
+}
+
+/// Anything that can be added to a `Schedule`: a system or an already configured `SystemConfig`.
+pub trait IntoSystemConfig<P> {
+    fn into_config(self) -> SystemConfig;
+
+    fn run_if(self, condition: impl FnMut(&World) -> bool + Send + Sync + 'static) -> SystemConfig
+    where
+        Self: Sized,
+    {
+        self.into_config().run_if(condition)
+    }
+
+    fn in_set(self, set: impl Into<String>) -> SystemConfig
+    where
+        Self: Sized,
+    {
+        self.into_config().in_set(set)
+    }
+
+    fn before(self, set: impl Into<String>) -> SystemConfig
+    where
+        Self: Sized,
+    {
+        self.into_config().before(set)
+    }
+
+    fn after(self, set: impl Into<String>) -> SystemConfig
+    where
+        Self: Sized,
+    {
+        self.into_config().after(set)
+    }
+}
+
+impl<P, S: System<P>> IntoSystemConfig<P> for S {
+    fn into_config(mut self) -> SystemConfig {
+        SystemConfig::new(Step::System(Box::new(move |world| Ok(self.run(world)?))))
+    }
+}
+
+impl IntoSystemConfig<SystemConfig> for SystemConfig {
+    fn into_config(self) -> SystemConfig {
+        self
+    }
+}
+
+/// Conditions and ordering shared by every system in a named set.
+#[derive(Default)]
+pub struct SetConfig {
+    conditions: Vec<Condition>,
+    before: Vec<String>,
+    after: Vec<String>,
+}
+
+impl SetConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only runs the set's systems when `condition` returns true.
+    pub fn run_if(mut self, condition: impl FnMut(&World) -> bool + Send + Sync + 'static) -> Self {
+        self.conditions.push(Box::new(condition));
+        self
+    }
+
+    /// Runs the set's systems before every system in another set.
+    pub fn before(mut self, set: impl Into<String>) -> Self {
+        self.before.push(set.into());
+        self
+    }
+
+    /// Runs the set's systems after every system in another set.
+    pub fn after(mut self, set: impl Into<String>) -> Self {
+        self.after.push(set.into());
+        self
+    }
+}
+
+struct ScheduledSystem {
+    step: Step,
+    /// Indices into `Schedule::sets`.
+    sets: Vec<usize>,
+    conditions: Vec<Condition>,
+    before: Vec<usize>,
+    after: Vec<usize>,
+}
+
+struct ScheduledSet {
+    name: String,
+    conditions: Vec<Condition>,
+    before: Vec<usize>,
+    after: Vec<usize>,
+}
+
+/// Runs systems in the order they were added, except where `before` and `after` constraints say otherwise.
+#[derive(Default)]
+pub struct Schedule {
+    systems: Vec<ScheduledSystem>,
+    sets: Vec<ScheduledSet>,
+    /// The order to run the systems in, worked out again after systems or sets are changed.
+    order: Option<Vec<usize>>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a system, optionally configured with `run_if`, `in_set`, `before` or `after`.
+    pub fn add_system<P>(&mut self, system: impl IntoSystemConfig<P>) -> &mut Self {
+        let config = system.into_config();
+        let system = ScheduledSystem {
+            step: config.step,
+            sets: self.set_indices(config.sets),
+            conditions: config.conditions,
+            before: self.set_indices(config.before),
+            after: self.set_indices(config.after),
+        };
+        self.systems.push(system);
+        self.order = None;
+        self
+    }
+
+    /// Adds a system with exclusive access to the world, which runs once the systems before it have finished.
+    pub fn add_exclusive_system(
+        &mut self,
+        system: impl FnMut(&mut World) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add_system(SystemConfig::exclusive(system))
+    }
+
+    /// Adds conditions and ordering constraints to every system in a set, including systems added later.
+    pub fn configure_set(&mut self, set: impl Into<String>, config: SetConfig) -> &mut Self {
+        let index = self.set_indices(vec![set.into()])[0];
+        let before = self.set_indices(config.before);
+        let after = self.set_indices(config.after);
+        let set = &mut self.sets[index];
+        set.conditions.extend(config.conditions);
+        set.before.extend(before);
+        set.after.extend(after);
+        self.order = None;
+        self
+    }
+
+    /// Runs every system once, skipping systems with a condition that returns false.
+    /// Each condition is evaluated at most once per run, just before the first system it applies to.
+    /// If a system's parameters cannot be fetched the error is returned and the remaining systems are not run.
+    /// If the ordering constraints form a cycle an error naming the sets involved is returned and no systems are run.
+    pub fn run(&mut self, world: &mut World) -> Result<(), Error> {
+        let order = match self.order.take() {
+            Some(order) => order,
+            None => self.system_order()?,
+        };
+
+        let mut set_results: Vec<Option<bool>> = vec![None; self.sets.len()];
+        let mut result = Ok(());
+        for i in order.iter() {
+            let system = &mut self.systems[*i];
+            let mut should_run = true;
+            for set in system.sets.iter() {
+                let set_result = *set_results[*set].get_or_insert_with(|| {
+                    self.sets[*set]
+                        .conditions
+                        .iter_mut()
+                        .all(|condition| condition(world))
+                });
+                if !set_result {
+                    should_run = false;
+                    break;
+                }
+            }
+            if !should_run
+                || !system
+                    .conditions
+                    .iter_mut()
+                    .all(|condition| condition(world))
+            {
+                continue;
+            }
+
+            result = match &mut system.step {
+                Step::System(system) => system(world),
+                Step::Exclusive(system) => system(world),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        self.order = Some(order);
+        result
+    }
+
+    /// Finds or creates the sets with the given names.
+    fn set_indices(&mut self, names: Vec<String>) -> Vec<usize> {
+        names
+            .into_iter()
+            .map(|name| {
+                if let Some(index) = self.sets.iter().position(|set| set.name == name) {
+                    index
+                } else {
+                    self.sets.push(ScheduledSet {
+                        name,
+                        conditions: Vec::new(),
+                        before: Vec::new(),
+                        after: Vec::new(),
+                    });
+                    self.sets.len() - 1
+                }
+            })
+            .collect()
+    }
+
+    /// Sorts the systems so every ordering constraint is met,
+    /// keeping systems in the order they were added where there is no constraint between them.
+    fn system_order(&self) -> Result<Vec<usize>, ScheduleCycle> {
+        let mut members = vec![Vec::new(); self.sets.len()];
+        for (i, system) in self.systems.iter().enumerate() {
+            for set in system.sets.iter() {
+                members[*set].push(i);
+            }
+        }
+
+        // `edges[a]` holds every system that must run after system `a`.
+        let mut edges = vec![Vec::new(); self.systems.len()];
+        for (i, system) in self.systems.iter().enumerate() {
+            let shared = system.sets.iter().map(|set| &self.sets[*set]);
+            let before = system
+                .before
+                .iter()
+                .chain(shared.clone().flat_map(|set| set.before.iter()));
+            let after = system
+                .after
+                .iter()
+                .chain(shared.flat_map(|set| set.after.iter()));
+            for other in before.flat_map(|set| members[*set].iter()) {
+                if *other != i {
+                    edges[i].push(*other);
+                }
+            }
+            for other in after.flat_map(|set| members[*set].iter()) {
+                if *other != i {
+                    edges[*other].push(i);
+                }
+            }
+        }
+
+        let mut incoming = vec![0; self.systems.len()];
+        for after in edges.iter().flatten() {
+            incoming[*after] += 1;
+        }
+        let mut ready: std::collections::BTreeSet<usize> = (0..self.systems.len())
+            .filter(|i| incoming[*i] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(self.systems.len());
+        while let Some(i) = ready.pop_first() {
+            order.push(i);
+            for after in edges[i].iter() {
+                incoming[*after] -= 1;
+                if incoming[*after] == 0 {
+                    ready.insert(*after);
+                }
+            }
+        }
+        if order.len() == self.systems.len() {
+            Ok(order)
+        } else {
+            Err(self.cycle_error(&edges, &incoming))
+        }
+    }
+
+    /// Names the sets whose ordering constraints link the systems left unsorted by `system_order`.
+    /// Systems that only come after a cycle are left out by peeling off systems with nothing left to run before.
+    fn cycle_error(&self, edges: &[Vec<usize>], incoming: &[usize]) -> ScheduleCycle {
+        let mut remaining: Vec<bool> = incoming.iter().map(|count| *count > 0).collect();
+        let mut outgoing: Vec<usize> = edges
+            .iter()
+            .map(|after| after.iter().filter(|i| remaining[**i]).count())
+            .collect();
+        let mut done: Vec<usize> = (0..self.systems.len())
+            .filter(|i| remaining[*i] && outgoing[*i] == 0)
+            .collect();
+        while let Some(i) = done.pop() {
+            remaining[i] = false;
+            for (before, after) in edges.iter().enumerate() {
+                for _ in after.iter().filter(|after| **after == i) {
+                    outgoing[before] -= 1;
+                    if remaining[before] && outgoing[before] == 0 {
+                        done.push(before);
+                    }
+                }
+            }
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        for (i, system) in self.systems.iter().enumerate() {
+            if !remaining[i] {
+                continue;
+            }
+            let shared = system.sets.iter().map(|set| &self.sets[*set]);
+            let constraints = system
+                .before
+                .iter()
+                .chain(system.after.iter())
+                .chain(shared.flat_map(|set| set.before.iter().chain(set.after.iter())));
+            for set in constraints {
+                let linked = self
+                    .systems
+                    .iter()
+                    .enumerate()
+                    .any(|(j, other)| j != i && remaining[j] && other.sets.contains(set));
+                if linked {
+                    names.push(self.sets[*set].name.clone());
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        ScheduleCycle::new(names)
+    }
+}
+
+impl World {
+    /// Runs a system once.
+    pub fn run<P>(&self, mut system: impl System<P>) -> Result<(), FetchError> {
+        system.run(self)
+    }
+
+    /// Takes the only instance of a component out of the world while `f` runs with both,
+    /// so a resource can be used alongside exclusive access to the world, then puts it back.
+    /// If `f` despawned the entity the resource was on, it is put back on a new entity.
+    /// An error is returned if no entity or more than one entity has the component.
+    ///
+    /// The resource is taken out with `remove_component` and put back with `add_component`,
+    /// so its entity moves to another archetype and back, any index on `T` is updated both times,
+    /// and the entity is given any components `T` requires that `f` removed from it.
+    /// If `f` added another `T`, the resource is still put back,
+    /// but `MultipleComponentsExist` is returned instead of `f`'s result.
+    pub fn resource_scope<T: Component, R>(
+        &mut self,
+        f: impl FnOnce(&mut World, &mut T) -> R,
+    ) -> Result<R, Error> {
+        let entity = self.single_entity::<T>()?;
+        let mut resource = self.remove_component::<T>(entity)?;
+        let result = f(self, &mut resource);
+        let added = !matches!(
+            self.single_entity::<T>(),
+            Err(FetchError::ComponentDoesNotExist(_))
+        );
+        if self.contains(entity) {
+            self.add_component(entity, resource)?;
+        } else {
+            self.spawn((resource,));
+        }
+        if added {
+            return Err(MultipleComponentsExist::new::<T>().into());
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position(f32);
+    struct Velocity(f32);
+    struct SpawnQueue(Vec<f32>);
+    #[derive(Debug, PartialEq)]
+    struct Count(usize);
+
+    fn movement(mut query: Query<(&mut Position, &Velocity)>) {
+        for (position, velocity) in query.iter() {
+            position.0 += velocity.0;
+        }
+    }
+
+    fn count(mut query: Query<(&Position,)>, count: &mut Count) {
+        count.0 = query.iter().count();
+    }
+
+    #[test]
+    fn test_schedule_exclusive_systems() {
+        let mut world = World::new();
+        world.spawn((Count(0),));
+        world.spawn((SpawnQueue(vec![1.0, 2.0]),));
+        world.spawn((Position(0.0), Velocity(1.0)));
+
+        let mut schedule = Schedule::new();
+        schedule
+            .add_system(movement)
+            .add_exclusive_system(|world: &mut World| {
+                world
+                    .resource_scope(|world, queue: &mut SpawnQueue| {
+                        for velocity in queue.0.drain(..) {
+                            world.spawn((Position(0.0), Velocity(velocity)));
+                        }
+                    })
+                    .unwrap();
+            })
+            .add_system(count);
+
+        schedule.run(&mut world).unwrap();
+        // Entities spawned by the exclusive system are seen by the systems after it.
+        assert_eq!(*world.get_single::<Count>().unwrap().inner(), Count(3));
+        assert!(world
+            .get_single::<SpawnQueue>()
+            .unwrap()
+            .inner()
+            .0
+            .is_empty());
+
+        schedule.run(&mut world).unwrap();
+        let mut positions: Vec<f32> = world
+            .query::<(&Position,)>()
+            .unwrap()
+            .iter()
+            .map(|p| p.0)
+            .collect();
+        positions.sort_by(f32::total_cmp);
+        assert_eq!(positions, vec![1.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn test_resource_scope() {
+        let mut world = World::new();
+        assert!(matches!(
+            world.resource_scope(|_, _: &mut Count| ()),
+            Err(Error::ComponentDoesNotExist(_))
+        ));
+
+        let holder = world.spawn((Count(1), Position(5.0)));
+        let seen = world
+            .resource_scope(|world, count: &mut Count| {
+                // The resource is out of the world while the scope runs.
+                assert!(world.get_single::<Count>().is_err());
+                count.0 += 1;
+                world.despawn(holder).unwrap();
+                count.0
+            })
+            .unwrap();
+        assert_eq!(seen, 2);
+        assert_eq!(*world.get_single::<Count>().unwrap().inner(), Count(2));
+
+        let mut world = World::new();
+        world.spawn((Count(1),));
+        world.run(|count: &mut Count| count.0 = 10).unwrap();
+        assert_eq!(*world.get_single::<Count>().unwrap().inner(), Count(10));
+    }
+
+    #[test]
+    fn test_resource_scope_duplicate() {
+        let mut world = World::new();
+        let holder = world.spawn((Count(1),));
+        let result = world.resource_scope(|world, _: &mut Count| {
+            world.spawn((Count(2),));
+        });
+        assert!(matches!(result, Err(Error::MultipleComponentsExist(_))));
+        assert_eq!(*world.get_component_mut::<Count>(holder).unwrap(), Count(1));
+        assert!(matches!(
+            world.get_single::<Count>(),
+            Err(FetchError::MultipleComponentsExist(_))
+        ));
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum GameState {
+        Playing,
+        Paused,
+    }
+    struct Log(Vec<&'static str>);
+
+    fn is_playing(world: &World) -> bool {
+        *world.get_single::<GameState>().unwrap().inner() == GameState::Playing
+    }
+
+    #[test]
+    fn test_schedule_sets_and_conditions() {
+        let mut world = World::new();
+        world.spawn((GameState::Playing,));
+        world.spawn((Log(Vec::new()),));
+
+        let evaluated = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = evaluated.clone();
+        let mut schedule = Schedule::new();
+        schedule
+            .add_system((|log: &mut Log| log.0.push("render")).after("gameplay"))
+            .add_system((|log: &mut Log| log.0.push("ai")).in_set("gameplay"))
+            .add_system(
+                (|log: &mut Log| log.0.push("physics"))
+                    .in_set("gameplay")
+                    .after("input"),
+            )
+            .add_system((|log: &mut Log| log.0.push("input")).in_set("input"))
+            .add_system(
+                (|log: &mut Log| log.0.push("menu")).run_if(|world: &World| !is_playing(world)),
+            )
+            .configure_set(
+                "gameplay",
+                SetConfig::new().run_if(move |world: &World| {
+                    counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    is_playing(world)
+                }),
+            );
+
+        schedule.run(&mut world).unwrap();
+        assert_eq!(
+            world.get_single::<Log>().unwrap().inner().0,
+            vec!["ai", "input", "physics", "render"]
+        );
+        // The set's condition is shared by both of its systems.
+        assert_eq!(evaluated.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        *world.get_single_mut::<GameState>().unwrap().inner() = GameState::Paused;
+        world.get_single_mut::<Log>().unwrap().inner().0.clear();
+        schedule.run(&mut world).unwrap();
+        assert_eq!(
+            world.get_single::<Log>().unwrap().inner().0,
+            vec!["input", "render", "menu"]
+        );
+        assert_eq!(evaluated.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_schedule_ordering_cycle() {
+        let mut world = World::new();
+        world.spawn((Count(0),));
+        let mut schedule = Schedule::new();
+        schedule
+            .add_system(|count: &mut Count| count.0 += 1)
+            .add_system((|| {}).in_set("a").after("b"))
+            .add_system((|| {}).in_set("b").after("a"))
+            .add_system((|| {}).in_set("c").after("a"));
+
+        // Only the sets in the cycle are named, not "c" which merely comes after it.
+        let error = schedule.run(&mut world).unwrap_err();
+        match &error {
+            Error::ScheduleCycle(e) => assert_eq!(e.sets(), &["a", "b"]),
+            _ => panic!("unexpected error {}", error),
+        }
+        assert!(error.to_string().contains("[a, b]"));
+        assert_eq!(*world.get_single::<Count>().unwrap().inner(), Count(0));
+        assert!(schedule.run(&mut world).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! Saving and loading a whole `World` with serde.
+//!
+//! Only components registered with `World::register_serializable` can be saved.
+//! A snapshot records every entity slot, including despawned ones, so `Entity` handles
+//! stored in components or elsewhere refer to the same entities after a restore.
+
+use std::collections::{BTreeMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+use crate::world::*;
+
+/// A serializable copy of every entity and registered component in a `World`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    /// Every entity slot in the world, indexed by `Entity::index`.
+    pub entities: Vec<EntitySnapshot>,
+    /// Despawned slots in the order they will be reused.
+    pub free_entities: Vec<EntityId>,
+}
+
+/// A single entity slot within a `WorldSnapshot`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EntitySnapshot {
+    pub generation: EntityId,
+    /// The entity's components keyed by their registered name.
+    /// `None` if the slot does not hold a live entity.
+    pub components: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+impl World {
+    /// Captures every entity and component in the world.
+    /// An error is returned if a component type has not been registered as serializable
+    /// or if a component is currently mutably borrowed.
+    pub fn snapshot(&self) -> Result<WorldSnapshot, Error> {
+        let mut entities: Vec<EntitySnapshot> = self
+            .entities
+            .iter()
+            .map(|entity_info| EntitySnapshot {
+                generation: entity_info.generation,
+                components: entity_info.alive.then(BTreeMap::new),
+            })
+            .collect();
+
+        // Serialize a column at a time so each lock is only taken once.
+        for archetype in self.archetypes.iter() {
+            for c in archetype.components.iter() {
+                let (name, serialize) = self
+                    .registry
+                    .info(c.id)
+                    .and_then(|info| Some((&info.name, info.serialize?)))
+                    .ok_or_else(|| ComponentNotRegistered::new(self.component_name(c.id)))?;
+
+                let values = serialize(&*c.data, 0..archetype.entities.len())?;
+                for (entity_index, value) in archetype.entities.iter().zip(values) {
+                    if let Some(components) = &mut entities[*entity_index as usize].components {
+                        components.insert(name.clone(), value);
+                    }
+                }
+            }
+        }
+
+        Ok(WorldSnapshot {
+            entities,
+            free_entities: self.free_entities.clone(),
+        })
+    }
+
+    /// Replaces every entity in the world with the entities in the snapshot.
+    /// Entities keep the index and generation they had when the snapshot was taken.
+    /// If an error is returned the world is left unchanged.
+    pub fn restore(&mut self, snapshot: &WorldSnapshot) -> Result<(), Error> {
+        let mut seen = HashSet::new();
+        for index in snapshot.free_entities.iter() {
+            let slot = snapshot.entities.get(*index as usize);
+            if slot.is_none_or(|slot| slot.components.is_some()) {
+                return Err(InvalidSnapshot::new(format!(
+                    "free entity slot {} is not a despawned entity",
+                    index
+                ))
+                .into());
+            }
+            // A repeated slot would be handed out to two entities.
+            if !seen.insert(*index) {
+                return Err(InvalidSnapshot::new(format!(
+                    "free entity slot {} appears more than once",
+                    index
+                ))
+                .into());
+            }
+        }
+
+        // Deserialize everything up front so a failure leaves the world untouched.
+        let mut rows = Vec::new();
+        for (index, entity) in snapshot.entities.iter().enumerate() {
+            if let Some(components) = &entity.components {
+                rows.push((index as EntityId, self.deserialize_components(components)?));
+            }
+        }
+
+        self.archetypes.clear();
+        self.bundle_id_to_archetype.clear();
+        self.entities = snapshot
+            .entities
+            .iter()
+            .map(|entity| EntityInfo {
+                generation: entity.generation,
+                location: EntityLocation {
+                    archetype_index: 0,
+                    index_in_archetype: 0,
+                },
+                alive: false,
+            })
+            .collect();
+        self.free_entities = snapshot.free_entities.clone();
+
+        // Entries for the cleared archetypes are dropped when the indices are rebuilt.
+        self.mark_indices_stale();
+        for (index, stores) in rows {
+            self.spawn_stores(index, stores);
+        }
+        Ok(())
+    }
+
+    /// Deserializes components keyed by their registered name into single row `ComponentStore`s.
+    pub(crate) fn deserialize_components(
+        &self,
+        components: &BTreeMap<String, serde_json::Value>,
+    ) -> Result<Vec<ComponentStore>, Error> {
+        let mut stores = Vec::with_capacity(components.len());
+        for (name, value) in components.iter() {
+            let deserialize = self
+                .registry
+                .id_of(name)
+                .and_then(|id| self.registry.info(id)?.deserialize)
+                .ok_or_else(|| ComponentNotRegistered::new(name.as_str()))?;
+            stores.push(deserialize(value)?);
+        }
+        Ok(stores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Target(Entity);
+
+    fn registered_world() -> World {
+        let mut world = World::new();
+        world.register_serializable::<Position>("Position").unwrap();
+        world.register_serializable::<Target>("Target").unwrap();
+        world.register_serializable::<String>("Name").unwrap();
+        world
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut world = registered_world();
+        let a = world.spawn((Position { x: 1.0, y: 2.0 }, String::from("a")));
+        let b = world.spawn((Position { x: 3.0, y: 4.0 },));
+        let c = world.spawn((String::from("c"), Target(a)));
+        world.despawn(b).unwrap();
+
+        let text = serde_json::to_string(&world.snapshot().unwrap()).unwrap();
+        let snapshot: WorldSnapshot = serde_json::from_str(&text).unwrap();
+
+        let mut restored = registered_world();
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot().unwrap(), snapshot);
+
+        assert!(!restored.contains(b));
+        let target = restored.get_component_mut::<Target>(c).unwrap().0;
+        assert_eq!(target, a);
+        assert_eq!(
+            *restored.get_component_mut::<Position>(target).unwrap(),
+            Position { x: 1.0, y: 2.0 }
+        );
+
+        // The despawned slot is reused just as it would have been in the original world.
+        let d = restored.spawn((Position { x: 0.0, y: 0.0 },));
+        assert_eq!(d, world.spawn((Position { x: 0.0, y: 0.0 },)));
+    }
+
+    #[test]
+    fn test_snapshot_rejects_repeated_free_slots() {
+        let mut world = registered_world();
+        world.spawn((Position { x: 1.0, y: 2.0 },));
+        let b = world.spawn((Position { x: 3.0, y: 4.0 },));
+        world.despawn(b).unwrap();
+        let mut snapshot = world.snapshot().unwrap();
+        snapshot.free_entities = vec![b.index, b.index];
+
+        let mut restored = registered_world();
+        let c = restored.spawn((String::from("c"),));
+        assert!(matches!(
+            restored.restore(&snapshot),
+            Err(Error::InvalidSnapshot(_))
+        ));
+        // The world is left unchanged.
+        assert_eq!(restored.get::<String>(c).unwrap().as_str(), "c");
+        assert!(restored.get::<Position>(c).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_unregistered_component() {
+        let mut world = registered_world();
+        world.spawn((Position { x: 1.0, y: 2.0 }, 5u8));
+        assert!(matches!(
+            world.snapshot(),
+            Err(Error::ComponentNotRegistered(_))
+        ));
+    }
+}
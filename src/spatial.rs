@@ -0,0 +1,503 @@
+//! Spatial indices over a position component for proximity queries.
+//!
+//! `World::create_spatial_index` buckets every entity with the chosen position component
+//! into a uniform grid of cubic cells, so `within_radius`, `within_aabb` and `k_nearest`
+//! only look at the cells near the query instead of every entity.
+//! The grid is kept up to date like any other index: spawning, adding, removing and despawning
+//! update it directly, and entities whose position component was mutably accessed are moved
+//! out of their previous cell and into their current one before the next query.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+use crate::index::*;
+use crate::world::*;
+
+/// A component giving the point an entity is at.
+/// 2D games can leave the third coordinate as zero.
+pub trait SpatialPosition: Component {
+    fn position(&self) -> [f32; 3];
+}
+
+pub(crate) struct SpatialIndex<P> {
+    grid: Mutex<Grid>,
+    stale: AtomicBool,
+    changes: Mutex<Changes>,
+    position: PhantomData<fn() -> P>,
+}
+
+type Cell = [i32; 3];
+
+struct Grid {
+    cell_size: f32,
+    cells: HashMap<Cell, Vec<(Entity, [f32; 3])>>,
+    positions: HashMap<Entity, [f32; 3]>,
+}
+
+impl Grid {
+    fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    fn cell_of(&self, point: [f32; 3]) -> Cell {
+        point.map(|x| (x / self.cell_size).floor() as i32)
+    }
+
+    fn insert(&mut self, entity: Entity, point: [f32; 3]) {
+        let cell = self.cell_of(point);
+        let previous = self.positions.get(&entity).map(|p| self.cell_of(*p));
+        if previous == Some(cell) {
+            // Most moves stay within a cell, so the entry is updated where it is.
+            self.positions.insert(entity, point);
+            let entries = self.cells.get_mut(&cell).unwrap();
+            entries.iter_mut().find(|(e, _)| *e == entity).unwrap().1 = point;
+            return;
+        }
+        self.remove(entity);
+        self.cells.entry(cell).or_default().push((entity, point));
+        self.positions.insert(entity, point);
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if let Some(point) = self.positions.remove(&entity) {
+            let cell = self.cell_of(point);
+            let entries = self.cells.get_mut(&cell).unwrap();
+            entries.retain(|(e, _)| *e != entity);
+            if entries.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    /// Calls `f` with every entry in the cells from `min` to `max` inclusive.
+    /// A range covering more cells than are occupied visits the occupied cells instead.
+    fn visit_cells(&self, min: Cell, max: Cell, mut f: impl FnMut(Entity, [f32; 3])) {
+        let volume = (0..3).fold(1i64, |volume, axis| {
+            volume.saturating_mul((max[axis] as i64 - min[axis] as i64 + 1).max(0))
+        });
+        if volume > self.cells.len() as i64 {
+            for (cell, entries) in self.cells.iter() {
+                if (0..3).all(|axis| min[axis] <= cell[axis] && cell[axis] <= max[axis]) {
+                    entries.iter().for_each(|(e, p)| f(*e, *p));
+                }
+            }
+        } else {
+            for x in min[0]..=max[0] {
+                for y in min[1]..=max[1] {
+                    for z in min[2]..=max[2] {
+                        if let Some(entries) = self.cells.get(&[x, y, z]) {
+                            entries.iter().for_each(|(e, p)| f(*e, *p));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn within_aabb(&self, min: [f32; 3], max: [f32; 3]) -> Vec<Entity> {
+        let mut found = Vec::new();
+        self.visit_cells(self.cell_of(min), self.cell_of(max), |entity, point| {
+            if (0..3).all(|axis| min[axis] <= point[axis] && point[axis] <= max[axis]) {
+                found.push(entity);
+            }
+        });
+        found
+    }
+
+    fn within_radius(&self, center: [f32; 3], radius: f32) -> Vec<Entity> {
+        let mut found = Vec::new();
+        let min = self.cell_of(center.map(|x| x - radius));
+        let max = self.cell_of(center.map(|x| x + radius));
+        self.visit_cells(min, max, |entity, point| {
+            if distance_squared(center, point) <= radius * radius {
+                found.push(entity);
+            }
+        });
+        found
+    }
+
+    fn k_nearest(&self, point: [f32; 3], k: usize) -> Vec<Entity> {
+        let center = self.cell_of(point);
+        let mut found: Vec<(f32, Entity)> = Vec::new();
+        // Search shells of cells outwards until the k nearest are known to have been seen.
+        let mut ring = 0i32;
+        while found.len() < self.positions.len() {
+            let side = 2 * ring as i64 + 1;
+            if side * side * side > self.cells.len() as i64 {
+                // Searching further is slower than looking at every entity.
+                found = self
+                    .positions
+                    .iter()
+                    .map(|(entity, p)| (distance_squared(point, *p), *entity))
+                    .collect();
+                break;
+            }
+
+            let min = center.map(|x| x.saturating_sub(ring));
+            let max = center.map(|x| x.saturating_add(ring));
+            for x in min[0]..=max[0] {
+                for y in min[1]..=max[1] {
+                    for z in min[2]..=max[2] {
+                        let cell = [x, y, z];
+                        let on_shell = (0..3).any(|axis| (cell[axis] - center[axis]).abs() == ring);
+                        if let (true, Some(entries)) = (on_shell, self.cells.get(&cell)) {
+                            found.extend(
+                                entries
+                                    .iter()
+                                    .map(|(e, p)| (distance_squared(point, *p), *e)),
+                            );
+                        }
+                    }
+                }
+            }
+
+            // Everything within this distance of the point is in the cells searched so far.
+            let covered = ring as f32 * self.cell_size;
+            if found
+                .iter()
+                .filter(|(d, _)| *d <= covered * covered)
+                .count()
+                >= k
+            {
+                break;
+            }
+            ring += 1;
+        }
+
+        found.sort_unstable_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        found.into_iter().take(k).map(|(_, e)| e).collect()
+    }
+}
+
+fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|axis| (a[axis] - b[axis]).powi(2)).sum()
+}
+
+impl<P: SpatialPosition> SpatialIndex<P> {
+    fn new(cell_size: f32) -> Self {
+        Self {
+            grid: Mutex::new(Grid::new(cell_size)),
+            changes: Mutex::new(Changes::default()),
+            // Built by the first query.
+            stale: AtomicBool::new(true),
+            position: PhantomData,
+        }
+    }
+
+    /// Runs a query on the grid, first rebuilding it if it is stale
+    /// or moving the entities whose positions may have changed.
+    fn query<R>(&self, world: &World, f: impl FnOnce(&Grid) -> R) -> Result<R, Error> {
+        let mut grid = self.grid.lock().unwrap();
+        // Cleared before building so a write that starts during the build marks it stale again.
+        if self.stale.swap(false, Ordering::AcqRel) {
+            let mut built = Grid::new(grid.cell_size);
+            if let Err(e) =
+                visit_components::<P>(world, |entity, p| built.insert(entity, p.position()))
+            {
+                self.stale.store(true, Ordering::Release);
+                return Err(e.into());
+            }
+            *grid = built;
+            self.changes.lock().unwrap().clear();
+        } else {
+            self.changes
+                .lock()
+                .unwrap()
+                .visit::<P>(world, |entity, p| grid.insert(entity, p.position()))?;
+        }
+        Ok(f(&grid))
+    }
+}
+
+impl<P: SpatialPosition> AnyIndex for SpatialIndex<P> {
+    fn to_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn insert(&mut self, entity: Entity, column: &mut dyn ComponentVec, row: usize) {
+        // A stale grid is rebuilt from scratch so there is no point updating it.
+        if !*self.stale.get_mut() {
+            let point = component_vec_to_mut::<P>(column)[row].position();
+            self.grid.get_mut().unwrap().insert(entity, point);
+        }
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        if !*self.stale.get_mut() {
+            self.grid.get_mut().unwrap().remove(entity);
+            self.changes.get_mut().unwrap().forget(entity);
+        }
+    }
+
+    fn mark_changed(&self, index: EntityId) {
+        self.changes.lock().unwrap().entity(index);
+    }
+
+    fn mark_archetype_changed(&self, archetype: usize) {
+        self.changes.lock().unwrap().archetype(archetype);
+    }
+
+    fn mark_stale(&self) {
+        self.stale.store(true, Ordering::Release);
+    }
+}
+
+impl World {
+    /// Starts tracking the positions of every entity with a `P` component in a grid of cubes
+    /// `cell_size` wide, replacing any existing spatial index over `P`.
+    /// Cells a little larger than the usual query radius work well.
+    pub fn create_spatial_index<P: SpatialPosition>(&mut self, cell_size: f32) {
+        assert!(cell_size > 0.0, "The cell size must be positive");
+        self.drop_spatial_index::<P>();
+        self.add_index(
+            ComponentId::of::<P>(),
+            Box::new(SpatialIndex::<P>::new(cell_size)),
+        );
+    }
+
+    /// Stops tracking the positions in `P`.
+    pub fn drop_spatial_index<P: SpatialPosition>(&mut self) {
+        self.remove_index::<SpatialIndex<P>>(ComponentId::of::<P>());
+    }
+
+    /// Finds every entity whose `P` is at most `radius` from `center`, in no particular order.
+    /// An error is returned if `P` has no spatial index, or if the index must be rebuilt
+    /// while `P` is mutably borrowed.
+    pub fn within_radius<P: SpatialPosition>(
+        &self,
+        center: [f32; 3],
+        radius: f32,
+    ) -> Result<Vec<Entity>, Error> {
+        self.spatial_index::<P>()?
+            .query(self, |grid| grid.within_radius(center, radius))
+    }
+
+    /// Finds every entity whose `P` is inside the box from `min` to `max`, in no particular order.
+    pub fn within_aabb<P: SpatialPosition>(
+        &self,
+        min: [f32; 3],
+        max: [f32; 3],
+    ) -> Result<Vec<Entity>, Error> {
+        self.spatial_index::<P>()?
+            .query(self, |grid| grid.within_aabb(min, max))
+    }
+
+    /// Finds the `k` entities whose `P` is closest to `point`, nearest first.
+    /// Fewer are returned if fewer entities have a `P`.
+    pub fn k_nearest<P: SpatialPosition>(
+        &self,
+        point: [f32; 3],
+        k: usize,
+    ) -> Result<Vec<Entity>, Error> {
+        self.spatial_index::<P>()?
+            .query(self, |grid| grid.k_nearest(point, k))
+    }
+
+    fn spatial_index<P: SpatialPosition>(&self) -> Result<&SpatialIndex<P>, NoSuchIndex> {
+        self.find_index::<SpatialIndex<P>>(ComponentId::of::<P>())
+            .ok_or_else(|| NoSuchIndex::new(std::any::type_name::<P>()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Position(f32, f32);
+
+    impl SpatialPosition for Position {
+        fn position(&self) -> [f32; 3] {
+            [self.0, self.1, 0.0]
+        }
+    }
+
+    fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+        entities.sort();
+        entities
+    }
+
+    #[test]
+    fn test_spatial_index_queries() {
+        let mut world = World::new();
+        world.create_spatial_index::<Position>(4.0);
+        let a = world.spawn((Position(0.0, 0.0),));
+        let b = world.spawn((Position(3.0, 0.0),));
+        let c = world.spawn((Position(-10.0, 5.0),));
+        let d = world.spawn((Position(100.0, 100.0),));
+
+        assert_eq!(
+            sorted(
+                world
+                    .within_radius::<Position>([0.0, 0.0, 0.0], 3.0)
+                    .unwrap()
+            ),
+            vec![a, b]
+        );
+        assert_eq!(
+            sorted(
+                world
+                    .within_aabb::<Position>([-20.0, -1.0, 0.0], [1.0, 10.0, 0.0])
+                    .unwrap()
+            ),
+            vec![a, c]
+        );
+        assert_eq!(
+            world.k_nearest::<Position>([2.0, 0.0, 0.0], 3).unwrap(),
+            vec![b, a, c]
+        );
+        assert_eq!(
+            world.k_nearest::<Position>([90.0, 90.0, 0.0], 10).unwrap(),
+            vec![d, b, a, c]
+        );
+
+        // The handles work with the rest of the world.
+        let near = world.k_nearest::<Position>([0.0, 0.0, 0.0], 1).unwrap();
+        assert_eq!(*world.get::<Position>(near[0]).unwrap(), Position(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_spatial_index_tracks_changes() {
+        let mut world = World::new();
+        let a = world.spawn((Position(0.0, 0.0),));
+        assert!(matches!(
+            world.within_radius::<Position>([0.0, 0.0, 0.0], 1.0),
+            Err(Error::NoSuchIndex(_))
+        ));
+        world.create_spatial_index::<Position>(1.0);
+        let b = world.spawn((1u8,));
+        world.add_component(b, Position(0.5, 0.5)).unwrap();
+        assert_eq!(
+            sorted(
+                world
+                    .within_radius::<Position>([0.0, 0.0, 0.0], 1.0)
+                    .unwrap()
+            ),
+            vec![a, b]
+        );
+
+        world.remove_component::<Position>(b).unwrap();
+        world.despawn(a).unwrap();
+        assert!(world
+            .within_radius::<Position>([0.0, 0.0, 0.0], 1.0)
+            .unwrap()
+            .is_empty());
+
+        let c = world.spawn((Position(50.0, 50.0),));
+        for position in world.query::<(&mut Position,)>().unwrap().iter() {
+            position.0 = -50.0;
+        }
+        assert_eq!(
+            world
+                .within_radius::<Position>([-50.0, 50.0, 0.0], 0.1)
+                .unwrap(),
+            vec![c]
+        );
+        assert!(world
+            .within_radius::<Position>([50.0, 50.0, 0.0], 0.1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_spatial_index_moves_changed_entities() {
+        let mut world = World::new();
+        world.create_spatial_index::<Position>(1.0);
+        let entities: Vec<Entity> = (0..100)
+            .map(|i| world.spawn((Position(i as f32, 0.0),)))
+            .collect();
+        let other = world.spawn((1u8, Position(0.0, 10.0)));
+        assert_eq!(
+            world
+                .within_radius::<Position>([5.0, 0.0, 0.0], 0.1)
+                .unwrap(),
+            vec![entities[5]]
+        );
+
+        // Moving within a cell and into another cell.
+        world.get_component_mut::<Position>(entities[5]).unwrap().0 = 5.5;
+        world.get_component_mut::<Position>(entities[6]).unwrap().1 = 20.0;
+        {
+            let index = world
+                .find_index::<SpatialIndex<Position>>(ComponentId::of::<Position>())
+                .unwrap();
+            assert!(!index.stale.load(Ordering::Acquire));
+        }
+        assert_eq!(
+            world
+                .within_radius::<Position>([5.5, 0.0, 0.0], 0.1)
+                .unwrap(),
+            vec![entities[5]]
+        );
+        assert!(world
+            .within_radius::<Position>([6.0, 0.0, 0.0], 0.1)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            world.k_nearest::<Position>([6.0, 20.0, 0.0], 1).unwrap(),
+            vec![entities[6]]
+        );
+
+        // A query moves only the archetypes it wrote to.
+        for (position, _) in world.query::<(&mut Position, &u8)>().unwrap().iter() {
+            position.1 = -10.0;
+        }
+        assert_eq!(
+            world
+                .within_aabb::<Position>([-1.0, -11.0, 0.0], [1.0, -9.0, 0.0])
+                .unwrap(),
+            vec![other]
+        );
+        assert_eq!(
+            sorted(
+                world
+                    .within_radius::<Position>([0.0, 0.0, 0.0], 1.0)
+                    .unwrap()
+            ),
+            vec![entities[0], entities[1]]
+        );
+    }
+
+    #[test]
+    fn test_spatial_index_changes_survive_archetype_moves() {
+        let mut world = World::new();
+        world.create_spatial_index::<Position>(1.0);
+        let e = world.spawn((Position(0.0, 0.0), 1u8));
+        assert_eq!(
+            world
+                .within_radius::<Position>([0.0, 0.0, 0.0], 0.1)
+                .unwrap(),
+            vec![e]
+        );
+
+        // The query marks the archetype the entity then leaves.
+        for position in world.query::<(&mut Position,)>().unwrap().iter() {
+            position.0 = 10.0;
+        }
+        world.remove_component::<u8>(e).unwrap();
+        assert_eq!(
+            world
+                .within_radius::<Position>([10.0, 0.0, 0.0], 0.1)
+                .unwrap(),
+            vec![e]
+        );
+        assert!(world
+            .within_aabb::<Position>([-1.0, -1.0, 0.0], [1.0, 1.0, 0.0])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            world.k_nearest::<Position>([0.0, 0.0, 0.0], 1).unwrap(),
+            vec![e]
+        );
+    }
+}
@@ -0,0 +1,394 @@
+//! Frame timing and fixed-timestep schedules.
+//!
+//! `Time` is a resource, the only instance of its component type, that `update_time` advances
+//! once per frame by the real time that has passed.
+//! A `FixedTimestep` is added to a schedule like a system and runs its own schedule at a fixed rate:
+//! each frame it adds the frame's delta to an accumulator and runs the inner schedule once for every
+//! whole step that has built up, which may be zero or several times.
+//! What is left over is published in the `FixedTime` resource as an interpolation alpha,
+//! so variable-rate systems such as rendering can blend between the last two fixed steps.
+//! Timesteps given different labels with `FixedTimestep::with_label` publish separate `FixedTime<L>` resources.
+
+use std::marker::PhantomData;
+use std::time::{Duration, Instant};
+
+use crate::error::*;
+use crate::query::*;
+use crate::schedule::*;
+use crate::world::*;
+
+/// The time between frames and since the first frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Time {
+    last_update: Option<Instant>,
+    delta: Duration,
+    elapsed: Duration,
+    frame_count: u64,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Time {
+    pub fn new() -> Self {
+        Self {
+            last_update: None,
+            delta: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            frame_count: 0,
+        }
+    }
+
+    /// The time between the last two updates.
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    /// The total of every delta so far.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed.as_secs_f32()
+    }
+
+    /// The number of updates so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Starts a new frame, measuring the real time since the last update.
+    /// The first update has a delta of zero.
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let delta = self
+            .last_update
+            .map_or(Duration::ZERO, |last| now.duration_since(last));
+        self.last_update = Some(now);
+        self.advance(delta);
+    }
+
+    /// Starts a new frame with a given delta instead of the real time, for example in tests or replays.
+    pub fn advance(&mut self, delta: Duration) {
+        self.delta = delta;
+        self.elapsed += delta;
+        self.frame_count += 1;
+    }
+}
+
+/// A system that advances the `Time` resource, normally the first system in the main schedule.
+pub fn update_time(time: &mut Time) {
+    time.update();
+}
+
+/// The state of the most recently run `FixedTimestep` with the label `L`.
+/// Each label has its own `FixedTime`, so timesteps running at different rates do not overwrite each other's.
+/// Timesteps that are never given a label share `FixedTime<()>`, which is what `&FixedTime` borrows.
+pub struct FixedTime<L: 'static = ()> {
+    step: Duration,
+    steps: u32,
+    alpha: f32,
+    label: PhantomData<fn() -> L>,
+}
+
+impl<L> Clone for FixedTime<L> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<L> Copy for FixedTime<L> {}
+
+impl<L> PartialEq for FixedTime<L> {
+    fn eq(&self, other: &Self) -> bool {
+        self.step == other.step && self.steps == other.steps && self.alpha == other.alpha
+    }
+}
+
+impl<L> std::fmt::Debug for FixedTime<L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FixedTime")
+            .field("step", &self.step)
+            .field("steps", &self.steps)
+            .field("alpha", &self.alpha)
+            .finish()
+    }
+}
+
+impl<L> FixedTime<L> {
+    /// The time each fixed step simulates, to be used as the delta by fixed-rate systems.
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    pub fn step_seconds(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    /// How many fixed steps were run this frame.
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    /// How far between the last fixed step and the next one the current frame is, from 0 up to 1.
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+}
+
+/// Runs a schedule at a fixed rate from within another schedule,
+/// publishing its state in the `FixedTime<L>` resource.
+pub struct FixedTimestep<L: 'static = ()> {
+    schedule: Schedule,
+    step: Duration,
+    accumulator: Duration,
+    max_steps: u32,
+    label: PhantomData<fn() -> L>,
+}
+
+impl FixedTimestep {
+    /// Runs `schedule` once every `step` of real time.
+    ///
+    /// # Panics
+    /// Panics if `step` is zero.
+    pub fn new(step: Duration, schedule: Schedule) -> Self {
+        assert!(!step.is_zero(), "A fixed timestep cannot be zero");
+        Self {
+            schedule,
+            step,
+            accumulator: Duration::ZERO,
+            max_steps: 8,
+            label: PhantomData,
+        }
+    }
+
+    /// Runs `schedule` `hz` times per second of real time.
+    ///
+    /// # Panics
+    /// Panics if `hz` is not a positive, finite number, or is so low or high that the step cannot be represented.
+    pub fn from_hz(hz: f64, schedule: Schedule) -> Self {
+        assert!(
+            hz.is_finite() && hz > 0.0,
+            "A fixed timestep's rate must be positive and finite, not {} hz",
+            hz
+        );
+        let step = Duration::try_from_secs_f64(1.0 / hz)
+            .unwrap_or_else(|_| panic!("A fixed timestep's rate of {} hz is too low", hz));
+        Self::new(step, schedule)
+    }
+}
+
+impl<L: 'static> FixedTimestep<L> {
+    /// Publishes this timestep's state in `FixedTime<M>` instead,
+    /// so it can run alongside other timesteps without them overwriting each other's state.
+    pub fn with_label<M: 'static>(self) -> FixedTimestep<M> {
+        FixedTimestep {
+            schedule: self.schedule,
+            step: self.step,
+            accumulator: self.accumulator,
+            max_steps: self.max_steps,
+            label: PhantomData,
+        }
+    }
+
+    /// Limits how many steps are run in one frame, 8 by default.
+    /// Time that would need more steps than this is dropped so a slow frame cannot cause
+    /// ever more steps in the frames that follow.
+    pub fn with_max_steps(mut self, max_steps: u32) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Adds the `Time` resource's delta to the accumulator and runs the schedule for every whole step in it,
+    /// then updates the `FixedTime<L>` resource, creating it if it does not exist.
+    pub fn run(&mut self, world: &mut World) -> Result<(), Error> {
+        self.accumulator += world.get_single::<Time>()?.inner().delta();
+
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            if steps == self.max_steps {
+                let remainder = self.accumulator.as_nanos() % self.step.as_nanos();
+                self.accumulator = Duration::from_nanos(remainder as u64);
+                break;
+            }
+            self.accumulator -= self.step;
+            steps += 1;
+            self.set_fixed_time(world, steps)?;
+            self.schedule.run(world)?;
+        }
+        self.set_fixed_time(world, steps)?;
+        Ok(())
+    }
+
+    fn set_fixed_time(&self, world: &mut World, steps: u32) -> Result<(), FetchError> {
+        let fixed_time = FixedTime::<L> {
+            step: self.step,
+            steps,
+            alpha: self.accumulator.as_secs_f32() / self.step.as_secs_f32(),
+            label: PhantomData,
+        };
+        match world.get_single_mut::<FixedTime<L>>() {
+            Ok(mut existing) => {
+                *existing.inner() = fixed_time;
+                return Ok(());
+            }
+            Err(FetchError::ComponentDoesNotExist(_)) => {}
+            Err(e) => return Err(e),
+        }
+        world.spawn((fixed_time,));
+        Ok(())
+    }
+}
+
+impl<L: 'static> IntoSystemConfig<FixedTimestep<L>> for FixedTimestep<L> {
+    fn into_config(mut self) -> SystemConfig {
+        SystemConfig::fallible_exclusive(move |world| self.run(world))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, PartialEq)]
+    struct PhysicsTicks(u32);
+    #[derive(Debug, Default, PartialEq)]
+    struct Alphas(Vec<f32>);
+
+    fn physics(ticks: &mut PhysicsTicks, fixed_time: &FixedTime) {
+        assert_eq!(fixed_time.step(), Duration::from_millis(10));
+        ticks.0 += 1;
+    }
+
+    fn render(alphas: &mut Alphas, fixed_time: &FixedTime) {
+        alphas.0.push(fixed_time.alpha());
+    }
+
+    #[test]
+    fn test_fixed_timestep() {
+        let mut world = World::new();
+        world.spawn((Time::new(),));
+        world.spawn((PhysicsTicks::default(), Alphas::default()));
+
+        let mut physics_schedule = Schedule::new();
+        physics_schedule.add_system(physics);
+        let mut schedule = Schedule::new();
+        schedule
+            .add_system(
+                FixedTimestep::new(Duration::from_millis(10), physics_schedule).in_set("fixed"),
+            )
+            .add_system(render.after("fixed"));
+
+        let mut ticks = Vec::new();
+        for delta in [4, 4, 4, 25, 0] {
+            world
+                .get_single_mut::<Time>()
+                .unwrap()
+                .inner()
+                .advance(Duration::from_millis(delta));
+            schedule.run(&mut world).unwrap();
+            ticks.push(world.get_single::<PhysicsTicks>().unwrap().inner().0);
+        }
+        assert_eq!(ticks, vec![0, 0, 1, 3, 3]);
+        assert_eq!(world.get_single::<Time>().unwrap().inner().frame_count(), 5);
+
+        let mut alphas = world.get_single::<Alphas>().unwrap();
+        let alphas = &alphas.inner().0;
+        let expected = [0.4, 0.8, 0.2, 0.7, 0.7];
+        assert_eq!(alphas.len(), expected.len());
+        for (alpha, expected) in alphas.iter().zip(expected) {
+            assert!((alpha - expected).abs() < 1e-4, "{} != {}", alpha, expected);
+        }
+    }
+
+    struct Network;
+    #[derive(Debug, Default, PartialEq)]
+    struct NetworkTicks(u32);
+
+    fn network(ticks: &mut NetworkTicks, fixed_time: &FixedTime<Network>) {
+        assert_eq!(fixed_time.step(), Duration::from_millis(50));
+        ticks.0 += 1;
+    }
+
+    #[test]
+    fn test_fixed_timestep_labels() {
+        let mut world = World::new();
+        world.spawn((
+            Time::new(),
+            PhysicsTicks::default(),
+            NetworkTicks::default(),
+        ));
+
+        let mut physics_schedule = Schedule::new();
+        physics_schedule.add_system(physics);
+        let mut network_schedule = Schedule::new();
+        network_schedule.add_system(network);
+        let mut schedule = Schedule::new();
+        schedule
+            .add_system(FixedTimestep::from_hz(100.0, physics_schedule))
+            .add_system(FixedTimestep::from_hz(20.0, network_schedule).with_label::<Network>());
+
+        world
+            .get_single_mut::<Time>()
+            .unwrap()
+            .inner()
+            .advance(Duration::from_millis(65));
+        schedule.run(&mut world).unwrap();
+        assert_eq!(world.get_single::<PhysicsTicks>().unwrap().inner().0, 6);
+        assert_eq!(world.get_single::<NetworkTicks>().unwrap().inner().0, 1);
+
+        // Each timestep keeps its own state rather than the last one to run overwriting the other's.
+        let physics_time = *world.get_single::<FixedTime>().unwrap().inner();
+        assert_eq!(physics_time.steps(), 6);
+        assert!((physics_time.alpha() - 0.5).abs() < 1e-4);
+        let network_time = *world.get_single::<FixedTime<Network>>().unwrap().inner();
+        assert_eq!(network_time.steps(), 1);
+        assert!((network_time.alpha() - 0.3).abs() < 1e-4);
+    }
+
+    #[test]
+    #[should_panic(expected = "must be positive and finite")]
+    fn test_fixed_timestep_zero_hz() {
+        FixedTimestep::from_hz(0.0, Schedule::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "must be positive and finite")]
+    fn test_fixed_timestep_nan_hz() {
+        FixedTimestep::from_hz(f64::NAN, Schedule::new());
+    }
+
+    #[test]
+    fn test_fixed_timestep_max_steps() {
+        let mut world = World::new();
+        world.spawn((Time::new(), PhysicsTicks::default()));
+        let mut physics_schedule = Schedule::new();
+        physics_schedule.add_system(physics);
+        let mut fixed = FixedTimestep::from_hz(100.0, physics_schedule).with_max_steps(3);
+
+        world
+            .get_single_mut::<Time>()
+            .unwrap()
+            .inner()
+            .advance(Duration::from_millis(1005));
+        fixed.run(&mut world).unwrap();
+        assert_eq!(world.get_single::<PhysicsTicks>().unwrap().inner().0, 3);
+        // The backlog is dropped but the partial step is kept.
+        let alpha = world.get_single::<FixedTime>().unwrap().inner().alpha();
+        assert!((alpha - 0.5).abs() < 1e-4);
+
+        let mut schedule = Schedule::new();
+        schedule.add_system(update_time);
+        schedule.run(&mut world).unwrap();
+        schedule.run(&mut world).unwrap();
+        assert_eq!(world.get_single::<Time>().unwrap().inner().frame_count(), 3);
+    }
+}
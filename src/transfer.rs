@@ -0,0 +1,336 @@
+//! Moving entities from one world to another.
+//!
+//! This lets a world be built somewhere else, such as a level loaded on another thread,
+//! and then moved into the live world. `World::merge` moves whole archetype columns at once
+//! rather than an entity at a time.
+//! Moved entities get new handles, and `Entity` fields inside components registered with
+//! `World::register_map_entities` are remapped to them.
+
+use std::collections::HashMap;
+
+use crate::archetype::*;
+use crate::component::*;
+use crate::entity::*;
+use crate::error::*;
+use crate::world::*;
+
+impl World {
+    /// Moves an entity and its components into another world, returning its handle there.
+    /// The entity is first removed from its hierarchy and relations as if it were despawned,
+    /// and any other `Entity` handles its components hold are replaced with `Entity::PLACEHOLDER`.
+    /// Nothing is moved if one of its dynamic components has a name `other` uses for a different component.
+    pub fn move_entity_to(&mut self, entity: Entity, other: &mut World) -> Result<Entity, Error> {
+        let ids = self.component_ids(entity)?;
+        other.check_imports(self, ids)?;
+        self.detach_hierarchy(entity);
+        let mut pending = Vec::new();
+        self.detach_relations(entity, &mut pending);
+        // A relation cycle may lead back to the entity being moved.
+        pending.retain(|e| *e != entity);
+        self.despawn_cascade(pending);
+
+        let mut stores = self.take_stores(entity.index);
+        self.release_entity(entity);
+        for c in stores.iter_mut() {
+            c.id = other.import_component(self, c.id);
+        }
+
+        let moved = other.reserve_entity();
+        other.spawn_stores(moved.index, stores);
+        other.map_entities(moved, &mut |e| {
+            if e == entity {
+                moved
+            } else {
+                Entity::PLACEHOLDER
+            }
+        });
+        Ok(moved)
+    }
+
+    /// Moves every entity in another world into this one and returns a map from their old handles to their new ones.
+    /// Hierarchies and relations among the moved entities are kept, while `Entity` handles
+    /// to anything else are replaced with `Entity::PLACEHOLDER`.
+    /// Relation kinds are carried over, but indices and required components in `other` are not,
+    /// and moved entities are not given components this world requires.
+    /// Nothing is moved if one of the dynamic components in `other` has a name this world uses for a different component.
+    pub fn merge(
+        &mut self,
+        mut other: World,
+    ) -> Result<HashMap<Entity, Entity>, ComponentNameConflict> {
+        let ids = other
+            .archetypes
+            .iter()
+            .filter(|archetype| !archetype.entities.is_empty())
+            .flat_map(|archetype| archetype.components.iter().map(|c| c.id));
+        self.check_imports(&other, ids)?;
+
+        for (id, hook) in other.relation_hooks.iter() {
+            self.relation_hooks.entry(*id).or_insert(*hook);
+        }
+        for (id, policy) in other.relation_policies.iter() {
+            self.relation_policies.entry(*id).or_insert(*policy);
+        }
+
+        let mut entity_map = HashMap::new();
+        for i in 0..other.archetypes.len() {
+            if other.archetypes[i].entities.is_empty() {
+                continue;
+            }
+            let mut source = std::mem::take(&mut other.archetypes[i]);
+            for c in source.components.iter_mut() {
+                c.id = self.import_component(&other, c.id);
+            }
+            source.components.sort_unstable_by_key(|c| c.id);
+
+            let ids: Vec<ComponentId> = source.components.iter().map(|c| c.id).collect();
+            let bundle_id = calculate_bundle_id(&ids);
+            let archetype_index =
+                if let Some(archetype_index) = self.bundle_id_to_archetype.get(&bundle_id) {
+                    *archetype_index
+                } else {
+                    let mut archetype = Archetype::new();
+                    for c in source.components.iter() {
+                        archetype.components.push(c.new_same_type());
+                    }
+                    let archetype_index = self.archetypes.len();
+                    self.bundle_id_to_archetype
+                        .insert(bundle_id, archetype_index);
+                    self.archetypes.push(archetype);
+                    archetype_index
+                };
+
+            let moved: Vec<Entity> = source
+                .entities
+                .iter()
+                .map(|index| {
+                    let old = Entity {
+                        index: *index,
+                        generation: other.entities[*index as usize].generation,
+                    };
+                    let new = self.reserve_entity();
+                    entity_map.insert(old, new);
+                    new
+                })
+                .collect();
+
+            let archetype = &mut self.archetypes[archetype_index];
+            let first_row = archetype.len();
+            for (c, destination) in source
+                .components
+                .iter_mut()
+                .zip(archetype.components.iter_mut())
+            {
+                c.data.append(&mut *destination.data);
+            }
+            archetype
+                .entities
+                .extend(moved.iter().map(|entity| entity.index));
+
+            for (row, entity) in moved.iter().enumerate() {
+                self.entities[entity.index as usize] = EntityInfo {
+                    location: EntityLocation {
+                        archetype_index: archetype_index as EntityId,
+                        index_in_archetype: (first_row + row) as EntityId,
+                    },
+                    generation: entity.generation,
+                    alive: true,
+                };
+                self.index_entity(entity.index);
+            }
+        }
+
+        let mut map = |entity| *entity_map.get(&entity).unwrap_or(&Entity::PLACEHOLDER);
+        for entity in entity_map.values() {
+            self.map_entities(*entity, &mut map);
+        }
+        Ok(entity_map)
+    }
+
+    /// Checks that the dynamic components among `ids` in another world can be matched up by name in this one,
+    /// so a move can fail before anything is taken out of either world.
+    pub(crate) fn check_imports(
+        &self,
+        other: &World,
+        ids: impl IntoIterator<Item = ComponentId>,
+    ) -> Result<(), ComponentNameConflict> {
+        for id in ids.into_iter().filter(|id| id.is_dynamic()) {
+            let info = other.registry.info(id).unwrap();
+            self.registry.check_dynamic(info.name(), info.layout())?;
+        }
+        Ok(())
+    }
+
+    /// Makes a component type from another world known to this one, returning its id here.
+    /// Dynamic components are matched by name since their ids differ between worlds,
+    /// which `check_imports` must have checked is possible.
+    pub(crate) fn import_component(&mut self, other: &World, id: ComponentId) -> ComponentId {
+        if id.is_dynamic() {
+            let info = other.registry.info(id).unwrap();
+            self.registry
+                .register_dynamic(info.name(), info.layout())
+                .unwrap()
+        } else {
+            self.registry.import(&other.registry, id);
+            id
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hierarchy::*;
+    use crate::query::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Name(String);
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Target(Entity);
+
+    impl MapEntities for Target {
+        fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+            self.0.map_entities(map);
+        }
+    }
+
+    #[test]
+    fn test_move_entity_to() {
+        let mut world = World::new();
+        world.register_map_entities::<Target>("Target").unwrap();
+        let parent = world.spawn((Name("parent".to_string()),));
+        let a = world.spawn((Name("a".to_string()),));
+        world.set_parent(a, parent).unwrap();
+        world.add_component(a, Target(parent)).unwrap();
+        let b = world.spawn((Name("b".to_string()),));
+        world.add_component(b, Target(b)).unwrap();
+
+        let mut other = World::new();
+        other.spawn((1u8,));
+        let moved_a = world.move_entity_to(a, &mut other).unwrap();
+        let moved_b = world.move_entity_to(b, &mut other).unwrap();
+        assert!(!world.contains(a) && !world.contains(b));
+        assert!(world.get::<Children>(parent).is_err());
+        assert!(matches!(
+            world.move_entity_to(a, &mut other),
+            Err(Error::NoSuchEntity(_))
+        ));
+
+        assert_eq!(other.get::<Name>(moved_a).unwrap().0, "a");
+        assert!(other.get::<Parent>(moved_a).is_err());
+        assert_eq!(
+            *other.get::<Target>(moved_a).unwrap(),
+            Target(Entity::PLACEHOLDER)
+        );
+        assert_eq!(*other.get::<Target>(moved_b).unwrap(), Target(moved_b));
+
+        // The freed slots are reused.
+        let c = world.spawn((Name("c".to_string()),));
+        assert!(c.index == a.index || c.index == b.index);
+        assert!(!world.contains(a) && !world.contains(b));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut live = World::new();
+        live.register_map_entities::<Target>("Target").unwrap();
+        live.create_index::<u32>();
+        let existing = live.spawn((Name("existing".to_string()), 1u32));
+
+        let mut staging = World::new();
+        let root = staging.spawn((Name("root".to_string()), 2u32));
+        let mut children = Vec::new();
+        for i in 0..100 {
+            let child = staging.spawn((Name(format!("child {}", i)), 3u32));
+            staging.set_parent(child, root).unwrap();
+            children.push(child);
+        }
+        let pointer = staging.spawn((Target(root),));
+        let dangling = staging.spawn((Target(Entity::PLACEHOLDER), 4u32));
+        staging.despawn(dangling).unwrap();
+        // Dynamic component ids are matched up by name.
+        live.register_dynamic_component("Other", std::alloc::Layout::new::<u8>())
+            .unwrap();
+        let bytes = staging
+            .register_dynamic_component("Bytes", std::alloc::Layout::new::<[u8; 4]>())
+            .unwrap();
+        let raw = staging.spawn((Name("raw".to_string()),));
+        staging.insert_dynamic(raw, bytes, &[1, 2, 3, 4]).unwrap();
+
+        let map = live.merge(staging).unwrap();
+        assert_eq!(map.len(), 103);
+        assert!(live.contains(existing));
+        let new_root = map[&root];
+        assert_eq!(live.get::<Name>(new_root).unwrap().0, "root");
+        let new_children: Vec<Entity> = children.iter().map(|child| map[child]).collect();
+        assert_eq!(
+            &**live.get::<Children>(new_root).unwrap(),
+            &new_children[..]
+        );
+        for child in new_children.iter() {
+            assert_eq!(live.get::<Parent>(*child).unwrap().get(), new_root);
+        }
+        assert_eq!(
+            *live.get::<Target>(map[&pointer]).unwrap(),
+            Target(new_root)
+        );
+        assert_eq!(live.lookup(&3u32).unwrap().len(), 100);
+        assert_eq!(live.lookup(&2u32).unwrap(), vec![new_root]);
+
+        let bytes = live.registry().id_of("Bytes").unwrap();
+        assert_eq!(&*live.get_dynamic(map[&raw], bytes).unwrap(), &[1, 2, 3, 4]);
+        assert_eq!(live.query::<(&Name,)>().unwrap().iter().count(), 103);
+    }
+
+    #[test]
+    fn test_merge_fills_missing_operations() {
+        // The live world has seen `Target` but only the staging world knows how to remap it.
+        let mut live = World::new();
+        let existing = live.spawn((Target(Entity::PLACEHOLDER),));
+
+        let mut staging = World::new();
+        staging.register_map_entities::<Target>("Target").unwrap();
+        let a = staging.spawn((Name("a".to_string()),));
+        let b = staging.spawn((Target(a),));
+
+        let map = live.merge(staging).unwrap();
+        assert_eq!(*live.get::<Target>(map[&b]).unwrap(), Target(map[&a]));
+        assert_eq!(
+            *live.get::<Target>(existing).unwrap(),
+            Target(Entity::PLACEHOLDER)
+        );
+    }
+
+    #[test]
+    fn test_merge_name_conflict() {
+        let mut live = World::new();
+        live.register_component::<Name>("Bytes").unwrap();
+        live.register_dynamic_component("Word", std::alloc::Layout::new::<u16>())
+            .unwrap();
+        let existing = live.spawn((1u8,));
+
+        let mut staging = World::new();
+        let bytes = staging
+            .register_dynamic_component("Bytes", std::alloc::Layout::new::<[u8; 4]>())
+            .unwrap();
+        let raw = staging.spawn((2u8,));
+        staging.insert_dynamic(raw, bytes, &[1, 2, 3, 4]).unwrap();
+        let word = staging
+            .register_dynamic_component("Word", std::alloc::Layout::new::<u32>())
+            .unwrap();
+        let wide = staging.spawn((3u8,));
+        staging.insert_dynamic(wide, word, &[0; 4]).unwrap();
+
+        // Neither entity is moved since neither dynamic component matches the live world's.
+        let e = staging.move_entity_to(raw, &mut live).unwrap_err();
+        assert!(matches!(e, Error::ComponentNameConflict(_)));
+        assert!(staging.contains(raw));
+        let e = staging.move_entity_to(wide, &mut live).unwrap_err();
+        assert!(matches!(e, Error::ComponentNameConflict(_)));
+        assert!(staging.contains(wide));
+
+        let e = live.merge(staging).unwrap_err();
+        assert!(e.name() == "Bytes" || e.name() == "Word");
+        assert_eq!(live.query::<(&u8,)>().unwrap().iter().count(), 1);
+        assert!(live.contains(existing));
+    }
+}
@@ -1,4 +1,3 @@
-use std::any::{TypeId};
 use std::collections::{hash_map::DefaultHasher, HashMap};
 use std::hash::{Hash, Hasher};
 
@@ -7,31 +6,145 @@ use crate::component::*;
 use crate::archetype::*;
 use crate::query::*;
 use crate::error::*;
+use crate::index::*;
+use crate::registry::*;
+use crate::relation::*;
+use crate::required::*;
 
 /// The world holds all components and associated entities.
 pub struct World {
     pub(crate) archetypes: Vec<Archetype>,
-    bundle_id_to_archetype: HashMap<u64, usize>,
+    pub(crate) bundle_id_to_archetype: HashMap<u64, usize>,
     pub(crate) entities: Vec<EntityInfo>,
-    free_entities: Vec<EntityId>,
+    pub(crate) free_entities: Vec<EntityId>,
+    generation_overflow: GenerationOverflow,
+    pub(crate) registry: ComponentRegistry,
+    /// Detaches a despawning entity from the relations stored in each relation component type.
+    pub(crate) relation_hooks: HashMap<ComponentId, DetachFn>,
+    /// What happens to sources when a target is despawned, keyed by the relation type.
+    pub(crate) relation_policies: HashMap<ComponentId, OnTargetDespawn>,
+    /// The indices kept up to date with each component type, such as those created with `create_index`.
+    pub(crate) indices: HashMap<ComponentId, Vec<Box<dyn AnyIndex>>>,
+    /// The components each component type requires, with constructors for when they are missing.
+    pub(crate) required: HashMap<ComponentId, Vec<(ComponentId, RequiredFn)>>,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl World {
     /// Create the world.
     pub fn new() -> Self {
-        Self {
+        let mut world = Self {
             archetypes: Vec::new(),
             bundle_id_to_archetype: HashMap::new(),
             entities: Vec::new(),
             free_entities: Vec::new(),
-        }
+            generation_overflow: GenerationOverflow::default(),
+            registry: ComponentRegistry::new(),
+            relation_hooks: HashMap::new(),
+            relation_policies: HashMap::new(),
+            indices: HashMap::new(),
+            required: HashMap::new(),
+        };
+        world.register_hierarchy();
+        world
     }
 
-    // Spawn new entity
-    pub fn spawn(&mut self, b: impl ComponentBundle) -> Entity {
+    /// The registry of component types known to this world.
+    pub fn registry(&self) -> &ComponentRegistry {
+        &self.registry
+    }
+
+    /// Registers `T` under `name` without attaching any operations.
+    /// This and the other `register_` functions return an error
+    /// if the name is already registered to another type.
+    pub fn register_component<T: Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<ComponentId, ComponentNameConflict> {
+        self.registry.register_component::<T>(name)
+    }
+
+    /// Registers `T` under `name` so its components can be cloned without knowing its type.
+    pub fn register_clone<T: Clone + Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<(), ComponentNameConflict> {
+        self.registry.register_clone::<T>(name)
+    }
+
+    /// Registers `T` under `name` so its components can be formatted with `debug_component`.
+    pub fn register_debug<T: std::fmt::Debug + Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<(), ComponentNameConflict> {
+        self.registry.register_debug::<T>(name)
+    }
+
+    /// Registers `T` under `name` so it can be added with `add_default_component`.
+    pub fn register_default<T: Default + Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<(), ComponentNameConflict> {
+        self.registry.register_default::<T>(name)
+    }
+
+    /// Registers `T` under `name` so it can be included in snapshots.
+    #[cfg(feature = "serde")]
+    pub fn register_serializable<T>(&mut self, name: &str) -> Result<(), ComponentNameConflict>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned + Component,
+    {
+        self.registry.register_serializable::<T>(name)
+    }
+
+    /// Registers `T` under `name` as plain-old-data so it can be included in rollback snapshots.
+    pub fn register_pod<T: bytemuck::Pod + Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<(), ComponentNameConflict> {
+        self.registry.register_pod::<T>(name)
+    }
+
+    /// Registers `T` under `name` as a component holding `Entity` handles.
+    /// The handles are updated when entities are recreated elsewhere, for example by a scene.
+    pub fn register_map_entities<T: MapEntities + Component>(
+        &mut self,
+        name: &str,
+    ) -> Result<(), ComponentNameConflict> {
+        self.registry.register_map_entities::<T>(name)
+    }
+
+    /// Sets what happens to an entity slot once its generation is exhausted.
+    /// By default the slot is retired.
+    pub fn set_generation_overflow(&mut self, policy: GenerationOverflow) {
+        self.generation_overflow = policy;
+    }
+
+    /// Returns true if the entity has not been despawned.
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entity_info(entity).is_some()
+    }
+
+    /// Looks up where a live entity is stored.
+    /// Handles that are out of range or whose generation does not match are rejected.
+    pub(crate) fn entity_info(&self, entity: Entity) -> Option<EntityInfo> {
+        self.entities
+            .get(entity.index as usize)
+            .filter(|entity_info| entity_info.alive && entity_info.generation == entity.generation)
+            .copied()
+    }
+
+    /// Picks the slot for a new entity, reusing a despawned slot if there is one.
+    /// The slot is not live until its components have been placed.
+    pub(crate) fn reserve_entity(&mut self) -> Entity {
+        // A freed slot's generation was already incremented when it was despawned.
         let (index, generation) = if let Some(index) = self.free_entities.pop() {
-            let (generation, _) = self.entities[index as usize].generation.overflowing_add(1);
-            (index, generation)
+            (index, self.entities[index as usize].generation)
         } else {
             // Push placeholder data
             self.entities.push(EntityInfo {
@@ -40,78 +153,371 @@ impl World {
                     index_in_archetype: 0,
                 },
                 generation: 0,
+                alive: false,
             });
 
             // Error if too many entities are allocated.
-            debug_assert!(self.entities.len() <= EntityId::MAX as usize);
+            // The last index is reserved for `Entity::PLACEHOLDER`.
+            assert!(
+                self.entities.len() <= EntityId::MAX as usize,
+                "Too many entities have been spawned"
+            );
             ((self.entities.len() - 1) as EntityId, 0)
         };
+        Entity { index, generation }
+    }
 
-        let location = b.spawn_in_world(self, index);
+    // Spawn new entity
+    /// # Panics
+    /// Panics if the bundle repeats a component type, which `try_spawn` returns as an error instead.
+    pub fn spawn(&mut self, b: impl ComponentBundle) -> Entity {
+        self.try_spawn(b).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Spawns a new entity, returning an error if the bundle repeats a component type.
+    /// Derived bundles reject repeated types at compile time where they can be seen,
+    /// but those coming from nested or generic bundles are only found here.
+    pub fn try_spawn(&mut self, b: impl ComponentBundle) -> Result<Entity, InvalidBundle> {
+        self.spawn_bundle(b)
+    }
+
+    /// Moves a bundle's components and any components they require into the archetype matching their types,
+    /// creating it if needed, and makes a new entity live there.
+    fn spawn_bundle<B: ComponentBundle>(&mut self, bundle: B) -> Result<Entity, InvalidBundle> {
+        let mut ids = Vec::new();
+        B::component_ids(&mut ids);
+        let required = self.required_stores(&ids);
+        ids.extend(required.iter().map(|c| c.id));
+        ids.sort_unstable();
+        let bundle_id = calculate_bundle_id(&ids);
+
+        // Find the appropriate archetype
+        // If it doesn't exist create a new archetype.
+        let archetype_index = if let Some(archetype) = self.bundle_id_to_archetype.get(&bundle_id) {
+            *archetype
+        } else {
+            B::register_types(&mut self.registry);
+            // A bundle with a repeated type never has an archetype, so it is always checked here.
+            if let Some(x) = ids.windows(2).find(|x| x[0] == x[1]) {
+                return Err(InvalidBundle::new(format!(
+                    "[{}] appears more than once",
+                    self.component_name(x[0])
+                )));
+            }
+            let mut archetype = Archetype::new();
+            B::add_columns(&mut archetype);
+            archetype
+                .components
+                .extend(required.iter().map(|c| c.new_same_type()));
+            archetype.components.sort_unstable_by_key(|c| c.id);
+            let index = self.archetypes.len();
+
+            self.bundle_id_to_archetype.insert(bundle_id, index);
+            self.archetypes.push(archetype);
+            index
+        };
+
+        let Entity { index, generation } = self.reserve_entity();
+        let archetype = &mut self.archetypes[archetype_index];
+        archetype.entities.push(index);
+        bundle.push_components(archetype);
+        for mut store in required {
+            let i = archetype
+                .components
+                .binary_search_by_key(&store.id, |c| c.id)
+                .unwrap();
+            store.data.migrate(0, &mut *archetype.components[i].data);
+        }
 
         self.entities[index as usize] = EntityInfo {
-            location,
-            generation: generation,
+            location: EntityLocation {
+                archetype_index: archetype_index as EntityId,
+                index_in_archetype: (archetype.len() - 1) as EntityId,
+            },
+            generation,
+            alive: true,
         };
+        self.index_entity(index);
+        Ok(Entity { index, generation })
+    }
 
-        Entity { index, generation }
+    /// Moves the components in single row `ComponentStore`s into the archetype matching their types
+    /// and makes the entity slot at `index` live.
+    /// The slot must exist and its generation must already be set.
+    pub(crate) fn spawn_stores(&mut self, index: EntityId, mut stores: Vec<ComponentStore>) {
+        stores.sort_unstable_by_key(|c| c.id);
+        debug_assert!(
+            stores.windows(2).all(|x| x[0].id != x[1].id),
+            "An entity cannot have duplicate component types"
+        );
+
+        let ids: Vec<ComponentId> = stores.iter().map(|c| c.id).collect();
+        let bundle_id = calculate_bundle_id(&ids);
+        let archetype_index =
+            if let Some(archetype_index) = self.bundle_id_to_archetype.get(&bundle_id) {
+                *archetype_index
+            } else {
+                let mut archetype = Archetype::new();
+                for c in stores.iter() {
+                    archetype.components.push(c.new_same_type());
+                }
+
+                let archetype_index = self.archetypes.len();
+                self.bundle_id_to_archetype
+                    .insert(bundle_id, archetype_index);
+                self.archetypes.push(archetype);
+                archetype_index
+            };
+
+        let archetype = &mut self.archetypes[archetype_index];
+        for (i, store) in stores.iter_mut().enumerate() {
+            store.data.migrate(0, &mut *archetype.components[i].data);
+        }
+        archetype.entities.push(index);
+
+        let entity_info = &mut self.entities[index as usize];
+        entity_info.location = EntityLocation {
+            archetype_index: archetype_index as EntityId,
+            index_in_archetype: (archetype.len() - 1) as EntityId,
+        };
+        entity_info.alive = true;
+        self.index_entity(index);
+    }
+
+    /// Moves every component of the live entity at `index` out of its archetype
+    /// into single row `ComponentStore`s, leaving the slot not live.
+    /// The slot's generation is unchanged and it is not added to the free list.
+    pub(crate) fn take_stores(&mut self, index: EntityId) -> Vec<ComponentStore> {
+        self.unindex_entity(index);
+        let location = self.entities[index as usize].location;
+        let archetype = &mut self.archetypes[location.archetype_index as usize];
+
+        let mut stores = Vec::with_capacity(archetype.components.len());
+        for c in archetype.components.iter_mut() {
+            let mut store = c.new_same_type();
+            c.data
+                .migrate(location.index_in_archetype, &mut *store.data);
+            stores.push(store);
+        }
+        archetype
+            .entities
+            .swap_remove(location.index_in_archetype as usize);
+
+        // Update the position of an entity that was moved.
+        if let Some(moved) = archetype.entities.get(location.index_in_archetype as usize) {
+            self.entities[*moved as usize].location = location;
+        }
+        self.entities[index as usize].alive = false;
+        stores
+    }
+
+    /// The name to report in errors about a component type.
+    pub(crate) fn component_name(&self, id: ComponentId) -> String {
+        self.registry
+            .name_of(id)
+            .map_or_else(|| format!("{:?}", id), str::to_string)
+    }
+
+    /// Lists the components an entity has.
+    pub fn component_ids(&self, entity: Entity) -> Result<Vec<ComponentId>, NoSuchEntity> {
+        let entity_info = self
+            .entity_info(entity)
+            .ok_or_else(|| NoSuchEntity::new(entity))?;
+        let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+        Ok(archetype.components.iter().map(|c| c.id).collect())
+    }
+
+    /// Formats one of an entity's components with the `Debug` implementation
+    /// of a type registered with `register_debug`.
+    pub fn debug_component(&self, entity: Entity, id: ComponentId) -> Result<String, Error> {
+        let entity_info = self
+            .entity_info(entity)
+            .ok_or_else(|| NoSuchEntity::new(entity))?;
+        let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+        let c = archetype
+            .components
+            .iter()
+            .find(|c| c.id == id)
+            .ok_or_else(|| EntityMissingComponent::with_name(entity, self.component_name(id)))?;
+        let debug = self
+            .registry
+            .info(id)
+            .and_then(|info| info.debug)
+            .ok_or_else(|| ComponentNotRegistered::new(self.component_name(id)))?;
+        debug(&*c.data, entity_info.location.index_in_archetype as usize)
+    }
+
+    /// Adds a default component of a type registered with `register_default` to an entity.
+    /// If the component already exists its data will be replaced.
+    pub fn add_default_component(&mut self, entity: Entity, id: ComponentId) -> Result<(), Error> {
+        if !self.contains(entity) {
+            return Err(NoSuchEntity::new(entity).into());
+        }
+        let default = self
+            .registry
+            .info(id)
+            .and_then(|info| info.default)
+            .ok_or_else(|| ComponentNotRegistered::new(self.component_name(id)))?;
+
+        let mut stores = self.take_stores(entity.index);
+        stores.retain(|c| c.id != id);
+        stores.push(default());
+        self.spawn_stores(entity.index, stores);
+        Ok(())
+    }
+
+    /// Updates the `Entity` handles held by an entity's components
+    /// for every component type registered with `register_map_entities`.
+    pub(crate) fn map_entities(&mut self, entity: Entity, map: &mut dyn FnMut(Entity) -> Entity) {
+        if let Some(entity_info) = self.entity_info(entity) {
+            let archetype = &mut self.archetypes[entity_info.location.archetype_index as usize];
+            let row = entity_info.location.index_in_archetype as usize;
+            for c in archetype.components.iter_mut() {
+                if let Some(map_entities) =
+                    self.registry.info(c.id).and_then(|i| i.map_entities)
+                {
+                    map_entities(&mut *c.data, row, map);
+                    for index in self.indices.get(&c.id).into_iter().flatten() {
+                        index.mark_changed(entity.index);
+                    }
+                }
+            }
+        }
     }
 
     /// Remove an entity and all its components from the world.
     /// An error is returned if the entity does not exist.
+    /// If the entity is in a hierarchy it is removed from its parent's `Children`
+    /// and its children are left without a parent.
+    /// Relations to and from the entity are cleaned up according to their `OnTargetDespawn` policy.
     pub fn despawn(&mut self, entity: Entity) -> Result<(), NoSuchEntity> {
+        if !self.contains(entity) {
+            return Err(NoSuchEntity::new(entity));
+        }
+        self.despawn_cascade(vec![entity]);
+        Ok(())
+    }
+
+    /// Despawns entities along with any sources their relation policies despawn with them.
+    /// Works through a list rather than recursing so a long chain of relations cannot overflow the stack.
+    pub(crate) fn despawn_cascade(&mut self, mut pending: Vec<Entity>) {
+        while let Some(entity) = pending.pop() {
+            // A cycle of relations can reach an entity more than once.
+            if !self.contains(entity) {
+                continue;
+            }
+            self.detach_hierarchy(entity);
+            self.detach_relations(entity, &mut pending);
+            let _ = self.despawn_detached(entity);
+        }
+    }
+
+    /// Despawns an entity without updating other entities that refer to it.
+    pub(crate) fn despawn_detached(&mut self, entity: Entity) -> Result<(), NoSuchEntity> {
         // Remove an entity
         // Update swapped entity position if an entity was moved.
-        let entity_info = self.entities[entity.index as usize];
-        if entity_info.generation == entity.generation {
-            self.entities[entity.index as usize].generation += 1;
+        if let Some(entity_info) = self.entity_info(entity) {
+            self.unindex_entity(entity.index);
             let moved_entity = self.archetypes[entity_info.location.archetype_index as usize]
                 .remove_entity(entity_info.location.index_in_archetype);
-            self.free_entities.push(entity.index);
+            self.release_entity(entity);
 
             // Update the position of an entity that was moved.
             self.entities[moved_entity as usize].location = entity_info.location;
 
             Ok(())
         } else {
-            Err(NoSuchEntity)
+            Err(NoSuchEntity::new(entity))
+        }
+    }
+
+    /// Marks the slot of an entity whose components have been removed as not live
+    /// and returns it to the free list with its next generation.
+    pub(crate) fn release_entity(&mut self, entity: Entity) {
+        // Live entities never have the retired generation so this cannot overflow.
+        let next_generation = entity.generation + 1;
+        let next_generation = if next_generation != RETIRED_GENERATION {
+            Some(next_generation)
+        } else {
+            match self.generation_overflow {
+                GenerationOverflow::Retire => None,
+                GenerationOverflow::Wrap => Some(0),
+            }
+        };
+
+        // A retired slot is not returned to the free list so it is never handed out again.
+        self.entities[entity.index as usize].alive = false;
+        if let Some(next_generation) = next_generation {
+            self.entities[entity.index as usize].generation = next_generation;
+            self.free_entities.push(entity.index);
+        } else {
+            self.entities[entity.index as usize].generation = RETIRED_GENERATION;
         }
     }
 
+    /// Gets immutable access to a single component on an `Entity`.
+    /// An error is returned if the component is currently mutably borrowed.
+    pub fn get<T: 'static>(&self, entity: Entity) -> Result<ComponentRef<'_, T>, Error> {
+        let entity_info = self
+            .entity_info(entity)
+            .ok_or_else(|| NoSuchEntity::new(entity))?;
+        let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+        let id = ComponentId::of::<T>();
+        let component_index = archetype
+            .components
+            .iter()
+            .position(|c| c.id == id)
+            .ok_or_else(|| EntityMissingComponent::new::<T>(entity))?;
+        let borrow = archetype
+            .get(component_index)
+            .try_read()
+            .map_err(|_| ComponentAlreadyBorrowed::new::<T>())?;
+        Ok(ComponentRef::new(
+            borrow,
+            entity_info.location.index_in_archetype as usize,
+        ))
+    }
+
     /// Gets mutable access to a single component on an `Entity`.
     pub fn get_component_mut<T: 'static>(
         &mut self,
         entity: Entity,
     ) -> Result<&mut T, ComponentError> {
-        let entity_info = self.entities[entity.index as usize];
-        if entity_info.generation == entity.generation {
+        if let Some(entity_info) = self.entity_info(entity) {
+            let id = ComponentId::of::<T>();
+            let archetype = &self.archetypes[entity_info.location.archetype_index as usize];
+            // The caller may change the value so indices of it must re-key the entity.
+            if archetype.components.iter().any(|c| c.id == id) {
+                self.mark_component_changed(id, entity.index);
+            }
             let archetype = &mut self.archetypes[entity_info.location.archetype_index as usize];
             archetype
                 .get_component_mut(entity_info.location.index_in_archetype)
-                .map_err(|e| ComponentError::EntityMissingComponent(e))
+                .ok_or_else(|| {
+                    ComponentError::EntityMissingComponent(EntityMissingComponent::new::<T>(entity))
+                })
         } else {
             // Entity no longer exists
-            Err(ComponentError::NoSuchEntity(NoSuchEntity))
+            Err(ComponentError::NoSuchEntity(NoSuchEntity::new(entity)))
         }
     }
 
     pub fn remove_component<T: 'static>(&mut self, entity: Entity) -> Result<T, ComponentError> {
-        let entity_info = self.entities[entity.index as usize];
-
-        if entity_info.generation == entity.generation {
+        if let Some(entity_info) = self.entity_info(entity) {
+            self.unindex_component(entity, ComponentId::of::<T>());
             let current_archetype = &self.archetypes[entity_info.location.archetype_index as usize];
 
-            let type_id = TypeId::of::<T>();
-            let mut type_ids: Vec<TypeId> = current_archetype
+            let id = ComponentId::of::<T>();
+            let mut ids: Vec<ComponentId> = current_archetype
                 .components
                 .iter()
-                .map(|c| c.type_id)
+                .map(|c| c.id)
                 .collect();
-            let binary_search_index = type_ids.binary_search(&type_id);
+            let binary_search_index = ids.binary_search(&id);
 
             if let Ok(remove_index) = binary_search_index {
-                type_ids.remove(remove_index);
-                let bundle_id = calculate_bundle_id(&type_ids);
+                ids.remove(remove_index);
+                let bundle_id = calculate_bundle_id(&ids);
                 let new_archetype_index = if let Some(new_archetype_index) =
                     self.bundle_id_to_archetype.get(&bundle_id)
                 {
@@ -120,7 +526,7 @@ impl World {
                     // Create a new archetype
                     let mut archetype = Archetype::new();
                     for c in current_archetype.components.iter() {
-                        if c.type_id != type_id {
+                        if c.id != id {
                             archetype.components.push(c.new_same_type());
                         }
                     }
@@ -179,24 +585,27 @@ impl World {
                     .swap_remove(entity_info.location.index_in_archetype as usize);
                 new_archetype.entities.push(entity.index);
 
-                Ok(
+                let removed =
                     component_vec_to_mut::<T>(&mut *old_archetype.components[remove_index].data)
-                        .swap_remove(entity_info.location.index_in_archetype as usize),
-                )
+                        .swap_remove(entity_info.location.index_in_archetype as usize);
+                // Changes marked on the old archetype no longer cover the entity, so it is indexed again.
+                self.index_entity(entity.index);
+                Ok(removed)
             } else {
                 // Component is not in entity
                 Err(ComponentError::EntityMissingComponent(
-                    EntityMissingComponent::new::<T>(entity.index),
+                    EntityMissingComponent::new::<T>(entity),
                 ))
             }
         } else {
             // Entity is not in world
-            Err(ComponentError::NoSuchEntity(NoSuchEntity))
+            Err(ComponentError::NoSuchEntity(NoSuchEntity::new(entity)))
         }
     }
 
     /// Adds a component to an entity.
     /// If the component already exists its data will be replaced.
+    /// Components it requires that the entity does not have are added with it.
     pub fn add_component<T: 'static + Send + Sync>(
         &mut self,
         entity: Entity,
@@ -208,19 +617,34 @@ impl World {
         // or migrated to an existing archetype.
 
         // First find if the entity exists
-        let entity_info = self.entities[entity.index as usize];
-        if entity_info.generation == entity.generation {
-            let type_id = TypeId::of::<T>();
+        if let Some(entity_info) = self.entity_info(entity) {
+            let id = ComponentId::of::<T>();
+
+            if !self.required.is_empty() {
+                let mut ids = self.component_ids(entity)?;
+                ids.push(id);
+                let required = self.required_stores(&ids);
+                if !required.is_empty() {
+                    // Move everything in one go rather than one archetype per missing component.
+                    self.registry.register_type::<T>();
+                    let mut stores = self.take_stores(entity.index);
+                    stores.retain(|c| c.id != id);
+                    stores.push(ComponentStore::from_value(t));
+                    stores.extend(required);
+                    self.spawn_stores(entity.index, stores);
+                    return Ok(());
+                }
+            }
 
             // First check if the component already exists for this entity.
             let current_archetype = &self.archetypes[entity_info.location.archetype_index as usize];
 
-            let mut type_ids: Vec<TypeId> = current_archetype
+            let mut ids: Vec<ComponentId> = current_archetype
                 .components
                 .iter()
-                .map(|c| c.type_id)
+                .map(|c| c.id)
                 .collect();
-            let binary_search_index = type_ids.binary_search(&type_id);
+            let binary_search_index = ids.binary_search(&id);
 
             if let Ok(insert_index) = binary_search_index {
                 // The component already exists, replace it.
@@ -239,8 +663,8 @@ impl World {
 
                 let insert_index = binary_search_index.unwrap_or_else(|i| i);
 
-                type_ids.insert(insert_index, type_id);
-                let bundle_id = calculate_bundle_id(&type_ids);
+                ids.insert(insert_index, id);
+                let bundle_id = calculate_bundle_id(&ids);
 
                 let new_archetype_index = if let Some(new_archetype_index) =
                     self.bundle_id_to_archetype.get(&bundle_id)
@@ -255,6 +679,7 @@ impl World {
                     }
 
                     let new_archetype_index = self.archetypes.len();
+                    self.registry.register_type::<T>();
                     archetype
                         .components
                         .insert(insert_index, ComponentStore::new::<T>());
@@ -313,48 +738,173 @@ impl World {
                     .swap_remove(entity_info.location.index_in_archetype as usize);
                 new_archetype.entities.push(entity.index);
             }
+            self.index_entity(entity.index);
 
             Ok(())
         } else {
-            Err(NoSuchEntity)
+            Err(NoSuchEntity::new(entity))
         }
     }
 
-    /// Query for an immutable reference to the first instance of a component found.
-    pub fn get_single<T: 'static>(&self) -> Result<Single<T>, FetchError> {
+    /// Query for an immutable reference to the only instance of a component.
+    /// An error is returned if no entity or more than one entity has the component,
+    /// or if the component is already mutably borrowed.
+    pub fn get_single<T: 'static>(&self) -> Result<Single<'_, T>, FetchError> {
         <&T>::fetch(self)
     }
 
-    /// Query for a mutable reference to the first instance of a component found.
-    pub fn get_single_mut<T: 'static>(&self) -> Result<SingleMut<T>, FetchError> {
+    /// Query for a mutable reference to the only instance of a component.
+    /// An error is returned if no entity or more than one entity has the component,
+    /// or if the component is already borrowed.
+    pub fn get_single_mut<T: 'static>(&self) -> Result<SingleMut<'_, T>, FetchError> {
         <&mut T>::fetch(self)
     }
 
+    /// Finds the entity holding the only instance of a component.
+    pub(crate) fn single_entity<T: 'static>(&self) -> Result<Entity, FetchError> {
+        let (archetype, _) = find_single::<T>(self)?;
+        let index = archetype.entities[0];
+        Ok(Entity {
+            index,
+            generation: self.entities[index as usize].generation,
+        })
+    }
+
     /// Get a query from the world.
     /// # Example
     /// ```
-    /// # use kudo::*;
+    /// # use kecs::world::*;
     /// # let mut world = World::new();
-    /// let query = world.query<(&bool, &String)>();
+    /// let query = world.query::<(&bool, &String)>();
     /// ```
-    pub fn query<'world_borrow, T: QueryParameters>(
-        &'world_borrow self,
-    ) -> Result<Query<T>, FetchError> {
+    pub fn query<T: QueryParameters>(&self) -> Result<Query<'_, T>, FetchError> {
         Ok(QueryFetch::<T>::fetch(self)?.take().unwrap())
     }
 
 }
 
-/// A bundle of Component 
-/// this shouldnt belongs to world.rs, no?
+/// A set of components that can be spawned together.
+/// It is implemented for tuples of up to 16 components and can be derived for structs with `#[derive(Bundle)]`.
+///
+/// A tuple is itself a valid component, so a tuple of tuples is not flattened:
+/// `((A, B), C)` spawns `(A, B)` as a single component alongside `C`.
+/// Since every type is a component, a nested tuple cannot also be treated as a bundle without the two overlapping.
+/// Bundles are combined with `chain` instead, which also builds bundles larger than a tuple allows.
+///
+/// Each field of a derived bundle is a component unless it is marked `#[bundle]`,
+/// in which case its components are flattened into the outer bundle.
+/// A component type appearing twice among the fields, or among the elements of a tuple marked `#[bundle]`,
+/// is rejected at compile time, including in generic bundles for types that do not use a generic parameter.
+/// Repeats coming from other nested bundles can only be found when spawning, see `World::try_spawn`.
+/// # Example
+/// ```
+/// # use kecs::{world::*, Bundle};
+/// #[derive(Bundle)]
+/// struct Physics {
+///     position: (f32, f32),
+///     mass: f32,
+/// }
+///
+/// #[derive(Bundle)]
+/// struct Ship {
+///     name: String,
+///     #[bundle]
+///     physics: Physics,
+/// }
+///
+/// let mut world = World::new();
+/// let ship = world.spawn(Ship {
+///     name: "Kestrel".to_string(),
+///     physics: Physics { position: (0.0, 0.0), mass: 10.0 },
+/// });
+/// assert_eq!(*world.get::<f32>(ship).unwrap(), 10.0);
+/// ```
+///
+/// ```compile_fail
+/// # use kecs::Bundle;
+/// #[derive(Bundle)]
+/// struct Health {
+///     current: u32,
+///     max: u32,
+/// }
+/// ```
+///
+/// ```compile_fail
+/// # use kecs::Bundle;
+/// #[derive(Bundle)]
+/// struct Tagged<T: Send + Sync + 'static> {
+///     value: T,
+///     id: u32,
+///     #[bundle]
+///     extra: (bool, u32),
+/// }
+/// ```
 pub trait ComponentBundle: 'static + Send + Sync {
+    /// Appends the id of every component in the bundle.
+    #[doc(hidden)]
+    fn component_ids(ids: &mut Vec<ComponentId>);
+    /// Adds every component type in the bundle to the registry.
+    #[doc(hidden)]
+    fn register_types(registry: &mut ComponentRegistry);
+    /// Adds an empty column for every component in the bundle, leaving the columns unsorted.
     #[doc(hidden)]
-    fn new_archetype(&self) -> Archetype;
+    fn add_columns(archetype: &mut Archetype);
+    /// Pushes every component onto its column in an archetype with exactly the bundle's components.
     #[doc(hidden)]
-    fn spawn_in_world(self, world: &mut World, entity_index: EntityId) -> EntityLocation;
+    fn push_components(self, archetype: &mut Archetype);
+
+    /// Combines this bundle with another so their components are spawned on the same entity.
+    /// # Example
+    /// ```
+    /// # use kecs::world::*;
+    /// # let mut world = World::new();
+    /// let physics = (1.0f32, 2.0f64);
+    /// let render = ("sprite", 3u8);
+    /// let entity = world.spawn(physics.chain(render));
+    /// assert_eq!(*world.get::<u8>(entity).unwrap(), 3);
+    /// ```
+    fn chain<B: ComponentBundle>(self, other: B) -> Chain<Self, B>
+    where
+        Self: Sized,
+    {
+        Chain(self, other)
+    }
 }
 
-fn calculate_bundle_id(types: &[TypeId]) -> u64 {
+/// Spawns an entity with no components.
+impl ComponentBundle for () {
+    fn component_ids(_ids: &mut Vec<ComponentId>) {}
+    fn register_types(_registry: &mut ComponentRegistry) {}
+    fn add_columns(_archetype: &mut Archetype) {}
+    fn push_components(self, _archetype: &mut Archetype) {}
+}
+
+/// Two bundles spawned as one, created by `ComponentBundle::chain`.
+pub struct Chain<A, B>(A, B);
+
+impl<A: ComponentBundle, B: ComponentBundle> ComponentBundle for Chain<A, B> {
+    fn component_ids(ids: &mut Vec<ComponentId>) {
+        A::component_ids(ids);
+        B::component_ids(ids);
+    }
+
+    fn register_types(registry: &mut ComponentRegistry) {
+        A::register_types(registry);
+        B::register_types(registry);
+    }
+
+    fn add_columns(archetype: &mut Archetype) {
+        A::add_columns(archetype);
+        B::add_columns(archetype);
+    }
+
+    fn push_components(self, archetype: &mut Archetype) {
+        self.0.push_components(archetype);
+        self.1.push_components(archetype);
+    }
+}
+
+pub(crate) fn calculate_bundle_id(types: &[ComponentId]) -> u64 {
     let mut s = DefaultHasher::new();
     types.hash(&mut s);
     s.finish()
@@ -372,71 +922,44 @@ fn index_twice<T>(slice: &mut [T], first: usize, second: usize) -> (&mut T, &mut
 }
 
 macro_rules! component_bundle_impl {
-    ($count: expr, $(($name: ident, $index: tt)),*) => {
-        impl< $($name: 'static + Send + Sync),*> ComponentBundle for ($($name,)*) {
-            fn new_archetype(&self) -> Archetype {
-                let mut components = vec![$(ComponentStore::new::<$name>()), *];
-                components.sort_unstable_by(|a, b| a.type_id.cmp(&b.type_id));
-                Archetype { components, entities: Vec::new() }
+    ($($name: ident),*) => {
+        impl<$($name: Component),*> ComponentBundle for ($($name,)*) {
+            fn component_ids(ids: &mut Vec<ComponentId>) {
+                $(ids.push(ComponentId::of::<$name>());)*
             }
 
-            fn spawn_in_world(self, world: &mut World, entity_index: EntityId) -> EntityLocation {
-                let mut types = [$(($index, TypeId::of::<$name>())), *];
-                types.sort_unstable_by(|a, b| a.1.cmp(&b.1));
-                debug_assert!(
-                    types.windows(2).all(|x| x[0].1 != x[1].1),
-                    "`ComponentBundle`s cannot have duplicate types"
-                );
-
-                // Is there a better way to map the original ordering to the sorted ordering?
-                let mut order = [0; $count];
-                for i in 0..order.len() {
-                    order[types[i].0] = i;
-                }
-                let types = [$(types[$index].1), *];
-
-                let bundle_id = calculate_bundle_id(&types);
-
-                // Find the appropriate archetype
-                // If it doesn't exist create a new archetype.
-                let archetype_index = if let Some(archetype) = world.bundle_id_to_archetype.get(&bundle_id) {
-                    *archetype
-                } else {
-                    let archetype = self.new_archetype();
-                    let index = world.archetypes.len();
+            fn register_types(registry: &mut ComponentRegistry) {
+                $(registry.register_type::<$name>();)*
+            }
 
-                    world.bundle_id_to_archetype.insert(bundle_id, index);
-                    world.archetypes.push(archetype);
-                    index
-                };
+            fn add_columns(archetype: &mut Archetype) {
+                $(archetype.components.push(ComponentStore::new::<$name>());)*
+            }
 
-                world.archetypes[archetype_index].entities.push(entity_index);
-                $(world.archetypes[archetype_index].push(order[$index], self.$index);)*
-                EntityLocation {
-                    archetype_index: archetype_index as EntityId,
-                    index_in_archetype: (world.archetypes[archetype_index].len() - 1) as EntityId
-                }
+            #[allow(non_snake_case)]
+            fn push_components(self, archetype: &mut Archetype) {
+                let ($($name,)*) = self;
+                $(archetype.push_component($name);)*
             }
         }
     }
 }
 
-component_bundle_impl! {1, (A, 0)}
-component_bundle_impl! {2, (A, 0), (B, 1)}
-component_bundle_impl! {3, (A, 0), (B, 1), (C, 2)}
-component_bundle_impl! {4, (A, 0), (B, 1), (C, 2), (D, 3)}
-component_bundle_impl! {5, (A, 0), (B, 1), (C, 2), (D, 3), (E, 4)}
-component_bundle_impl! {6, (A, 0), (B, 1), (C, 2), (D, 3), (E, 4), (F, 5)}
-component_bundle_impl! {7, (A, 0), (B, 1), (C, 2), (D, 3), (E, 4), (F, 5), (G, 6)}
-component_bundle_impl! {8, (A, 0), (B, 1), (C, 2), (D, 3), (E, 4), (F, 5), (G, 6), (H, 7)}
-component_bundle_impl! {9, (A, 0), (B, 1), (C, 2), (D, 3), (E, 4), (F, 5), (G, 6), (H, 7), (I, 8)}
-component_bundle_impl! {10, (A, 0), (B, 1), (C, 2), (D, 3), (E, 4), (F, 5), (G, 6), (H, 7), (I, 8), (J, 9)}
-component_bundle_impl! {11, (A, 0), (B, 1), (C, 2), (D, 3), (E, 4), (F, 5), (G, 6), (H, 7), (I, 8), (J, 9), (K, 10)}
-component_bundle_impl! {12, (A, 0), (B, 1), (C, 2), (D, 3), (E, 4), (F, 5), (G, 6), (H, 7), (I, 8), (J, 9), (K, 10), (L, 11)}
+/// Implements `ComponentBundle` for a tuple of every length up to the number of names given.
+macro_rules! component_bundle_impls {
+    ($head: ident $(, $tail: ident)*) => {
+        component_bundle_impl!($head $(, $tail)*);
+        component_bundle_impls!($($tail),*);
+    };
+    () => {};
+}
+
+component_bundle_impls!(P, O, N, M, L, K, J, I, H, G, F, E, D, C, B, A);
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_world_spawn_entities() {
@@ -481,6 +1004,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(unused_variables)]
     fn test_world_query() {
         let mut world = World::new();
         #[derive(Debug)]
@@ -500,5 +1024,301 @@ mod tests {
             println!("{}, {}", component.0.id, component.0.name);
         }
     }
-}
 
+    #[test]
+    fn test_world_despawn_reuses_slot() {
+        let mut world = World::new();
+        let entity_0 = world.spawn((1,));
+        world.despawn(entity_0).unwrap();
+        let entity_1 = world.spawn((2,));
+        assert_eq!(entity_0.index, entity_1.index);
+        assert_eq!(entity_1.generation, entity_0.generation + 1);
+        assert!(world.despawn(entity_0).is_err());
+        assert!(world.despawn(entity_1).is_ok());
+    }
+
+    #[test]
+    fn test_world_generation_overflow_retire() {
+        let mut world = World::new();
+        let entity_0 = world.spawn((1,));
+        world.despawn(entity_0).unwrap();
+
+        // Force the slot to its last usable generation.
+        world.entities[entity_0.index as usize].generation = RETIRED_GENERATION - 1;
+        let entity_1 = world.spawn((2,));
+        assert_eq!(entity_1.generation, RETIRED_GENERATION - 1);
+        world.despawn(entity_1).unwrap();
+
+        // The slot must not be handed out again.
+        let entity_2 = world.spawn((3,));
+        assert_ne!(entity_2.index, entity_1.index);
+        assert!(world.despawn(entity_1).is_err());
+        assert_eq!(world.entities.len(), 2);
+    }
+
+    #[test]
+    fn test_world_generation_overflow_wrap() {
+        let mut world = World::new();
+        world.set_generation_overflow(GenerationOverflow::Wrap);
+        let entity_0 = world.spawn((1,));
+        world.despawn(entity_0).unwrap();
+
+        world.entities[entity_0.index as usize].generation = RETIRED_GENERATION - 1;
+        let entity_1 = world.spawn((2,));
+        world.despawn(entity_1).unwrap();
+
+        let entity_2 = world.spawn((3,));
+        assert_eq!(entity_2.index, entity_1.index);
+        assert_eq!(entity_2.generation, 0);
+        assert!(world.despawn(entity_1).is_err());
+        assert!(world.despawn(entity_2).is_ok());
+    }
+
+    #[test]
+    fn test_world_rejects_out_of_range_entities() {
+        let mut world = World::new();
+        let entity = world.spawn((1,));
+        let other = Entity {
+            index: entity.index + 10,
+            generation: 0,
+        };
+        assert!(world.despawn(other).is_err());
+        assert!(world.despawn(Entity::PLACEHOLDER).is_err());
+        assert!(world.add_component(other, true).is_err());
+        assert!(matches!(
+            world.get_component_mut::<i32>(other),
+            Err(ComponentError::NoSuchEntity(_))
+        ));
+        assert!(matches!(
+            world.remove_component::<i32>(other),
+            Err(ComponentError::NoSuchEntity(_))
+        ));
+        assert!(world.contains(entity));
+    }
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Spawn,
+        Despawn(Entity),
+        Add(Entity),
+        Remove(Entity),
+        Get(Entity),
+    }
+
+    fn entity_strategy() -> impl Strategy<Value = Entity> {
+        prop_oneof![
+            // Small handles often collide with real ones.
+            (0..8 as EntityId, 0..3 as EntityId)
+                .prop_map(|(index, generation)| Entity { index, generation }),
+            (any::<EntityId>(), any::<EntityId>())
+                .prop_map(|(index, generation)| Entity { index, generation }),
+        ]
+    }
+
+    fn op_strategy() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            Just(Op::Spawn),
+            entity_strategy().prop_map(Op::Despawn),
+            entity_strategy().prop_map(Op::Add),
+            entity_strategy().prop_map(Op::Remove),
+            entity_strategy().prop_map(Op::Get),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_world_random_entity_handles(ops in proptest::collection::vec(op_strategy(), 0..64)) {
+            let mut world = World::new();
+            // Every live entity and whether it has a `u8` component.
+            let mut live: HashMap<Entity, bool> = HashMap::new();
+
+            for op in ops {
+                match op {
+                    Op::Spawn => {
+                        let entity = world.spawn((0u32,));
+                        prop_assert!(live.insert(entity, false).is_none());
+                    }
+                    Op::Despawn(entity) => {
+                        prop_assert_eq!(world.despawn(entity).is_ok(), live.remove(&entity).is_some());
+                    }
+                    Op::Add(entity) => {
+                        let result = world.add_component(entity, 1u8);
+                        prop_assert_eq!(result.is_ok(), live.contains_key(&entity));
+                        if let Some(has_u8) = live.get_mut(&entity) {
+                            *has_u8 = true;
+                        }
+                    }
+                    Op::Remove(entity) => {
+                        let result = world.remove_component::<u8>(entity);
+                        match live.get_mut(&entity) {
+                            Some(has_u8) => {
+                                prop_assert_eq!(result.is_ok(), *has_u8);
+                                *has_u8 = false;
+                            }
+                            None => prop_assert!(matches!(result, Err(ComponentError::NoSuchEntity(_)))),
+                        }
+                    }
+                    Op::Get(entity) => {
+                        let result = world.get_component_mut::<u8>(entity).map(|v| *v);
+                        match live.get(&entity) {
+                            Some(true) => prop_assert_eq!(result.ok(), Some(1)),
+                            Some(false) => prop_assert!(matches!(result, Err(ComponentError::EntityMissingComponent(_)))),
+                            None => prop_assert!(matches!(result, Err(ComponentError::NoSuchEntity(_)))),
+                        }
+                    }
+                }
+            }
+
+            for entity in live.keys() {
+                prop_assert!(world.contains(*entity));
+            }
+        }
+    }
+
+    #[test]
+    fn test_world_get_single() {
+        let mut world = World::new();
+        assert!(matches!(
+            world.get_single::<u8>(),
+            Err(FetchError::ComponentDoesNotExist(_))
+        ));
+
+        // Leaves an empty archetype with a `u8` column in front of the populated one.
+        let entity = world.spawn((5u8, true));
+        world.remove_component::<bool>(entity).unwrap();
+        assert_eq!(*world.get_single::<u8>().unwrap().inner(), 5);
+
+        {
+            let _borrow = world.get_single_mut::<u8>().unwrap();
+            assert!(matches!(
+                world.get_single::<u8>(),
+                Err(FetchError::ComponentAlreadyBorrowed(_))
+            ));
+        }
+
+        world.spawn((6u8,));
+        assert!(matches!(
+            world.get_single_mut::<u8>(),
+            Err(FetchError::MultipleComponentsExist(_))
+        ));
+    }
+
+    #[test]
+    fn test_world_derived_bundles() {
+        use crate::Bundle;
+
+        #[derive(Bundle)]
+        struct Velocity(f32, u8);
+
+        #[derive(Bundle)]
+        struct Tagged<T: Component> {
+            tag: T,
+            #[bundle]
+            velocity: Velocity,
+        }
+
+        let mut world = World::new();
+        let a = world.spawn(Tagged {
+            tag: "ship",
+            velocity: Velocity(1.5, 2),
+        });
+        // A tuple with the same components lands in the same archetype.
+        let b = world.spawn((3u8, "rock", 0.5f32));
+        assert_eq!(world.archetypes.len(), 1);
+        assert_eq!(*world.get::<&str>(a).unwrap(), "ship");
+        assert_eq!(*world.get::<f32>(a).unwrap(), 1.5);
+        assert_eq!(*world.get::<u8>(a).unwrap(), 2);
+        assert_eq!(*world.get::<u8>(b).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_world_chained_bundles() {
+        let mut world = World::new();
+        let big = (
+            0u8, 1u16, 2u32, 3u64, 4u128, 5i8, 6i16, 7i32, 8i64, 9i128, 10usize, 11isize, 12f32,
+            13f64, 'e', "15",
+        );
+        let entity = world.spawn(big.chain((true, String::from("17"))).chain((Entity::PLACEHOLDER,)));
+        assert_eq!(world.component_ids(entity).unwrap().len(), 19);
+        assert_eq!(*world.get::<u128>(entity).unwrap(), 4);
+        assert_eq!(*world.get::<&str>(entity).unwrap(), "15");
+        assert_eq!(*world.get::<String>(entity).unwrap(), "17");
+
+        // Chaining in a different order reaches the same archetype.
+        world.spawn((1.0f32,).chain((2u8,)));
+        world.spawn((3u8, 4.0f32));
+        assert_eq!(world.archetypes.len(), 2);
+    }
+
+    #[test]
+    fn test_world_nested_bundle_duplicates() {
+        use crate::Bundle;
+
+        #[derive(Bundle)]
+        struct Inner(u8);
+
+        #[derive(Bundle)]
+        struct Outer {
+            id: u8,
+            #[bundle]
+            inner: Inner,
+        }
+
+        #[derive(Bundle)]
+        struct Pair(u8, u16);
+
+        #[derive(Bundle)]
+        struct Both {
+            #[bundle]
+            first: Inner,
+            #[bundle]
+            second: Pair,
+        }
+
+        // The nested duplicate cannot be seen by the derive so it is found when spawning.
+        let mut world = World::new();
+        let error = world
+            .try_spawn(Outer {
+                id: 1,
+                inner: Inner(2),
+            })
+            .unwrap_err();
+        assert!(error.to_string().contains("[u8] appears more than once"));
+        // Neither can a type shared by two nested bundles.
+        let error = world
+            .try_spawn(Both {
+                first: Inner(1),
+                second: Pair(2, 3),
+            })
+            .unwrap_err();
+        assert!(error.to_string().contains("[u8] appears more than once"));
+        assert!(world.try_spawn((1u8, 2u16).chain((3u8,))).is_err());
+        // Nothing was spawned.
+        assert!(world.entities.is_empty());
+        assert!(world.try_spawn((1u8, 2u16)).is_ok());
+    }
+
+    #[test]
+    fn test_world_nested_tuples() {
+        #[derive(Debug, PartialEq)]
+        struct A(u8);
+        struct B;
+        struct C;
+        struct D;
+        #[derive(Debug, PartialEq)]
+        struct E(u8);
+
+        let mut world = World::new();
+        // A tuple inside a tuple bundle is a component of its own.
+        let point = world.spawn(((1.0f32, 2.0f32), 1u8));
+        assert_eq!(*world.get::<(f32, f32)>(point).unwrap(), (1.0, 2.0));
+        assert_eq!(world.component_ids(point).unwrap().len(), 2);
+
+        // Chained bundles are flattened onto one entity.
+        let entity = world.spawn((A(1), B).chain((C, D, E(2))));
+        let mut query = world.query::<(&A, &E)>().unwrap();
+        let found: Vec<_> = query.iter().collect();
+        assert_eq!(found, vec![(&A(1), &E(2))]);
+        assert_eq!(world.component_ids(entity).unwrap().len(), 5);
+    }
+}